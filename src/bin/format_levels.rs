@@ -0,0 +1,74 @@
+//! Rewrites level files in the canonical layout.
+//!
+//! cargo run --bin format_levels -- [--compact | --spaced] [--check] <files or folders>
+
+use brix::game::levels::{self, Encoding};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Level files named on the command line, folders standing for every
+/// `.txt` file in them
+fn level_files(paths: &[String]) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .filter(|p| p.extension().is_some_and(|e| e == "txt"))
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    files
+}
+
+fn main() {
+    let mut encoding = None;
+    let mut check = false;
+    let mut paths = vec![];
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--compact" => encoding = Some(Encoding::Compact),
+            "--spaced" => encoding = Some(Encoding::Spaced),
+            "--check" => check = true,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("usage: format_levels [--compact | --spaced] [--check] <files or folders>");
+        process::exit(2);
+    }
+
+    let mut unformatted = 0;
+    for file in level_files(&paths) {
+        let text = fs::read_to_string(&file).unwrap();
+        let formatted = match levels::format_level(&text, encoding) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                process::exit(1);
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        unformatted += 1;
+        if check {
+            println!("{} is not formatted", file.display());
+        } else {
+            fs::write(&file, formatted).unwrap();
+            println!("Formatted {}", file.display());
+        }
+    }
+    if check && unformatted > 0 {
+        process::exit(1);
+    }
+}
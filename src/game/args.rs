@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use super::config::{Config, MAX_SCALE};
+
+pub const USAGE: &str = "usage: brix [--level W-S | --level N] [--pack PATH] [--levels-dir DIR] \
+[--fullscreen] [--scale N] [--mute]";
+
+/// What the game was started with, overriding the saved settings for this run
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Args {
+    pub level: Option<String>, // a level name such as 5-3, or its number
+    pub pack: Option<PathBuf>,
+    pub levels_dir: Option<PathBuf>, // folder `pack` is looked for in
+    pub fullscreen: bool,
+    pub scale: Option<u32>,
+    pub mute: bool,
+}
+
+impl Args {
+    /// Reads the arguments that follow the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--level" => parsed.level = Some(value()?),
+                "--pack" => parsed.pack = Some(PathBuf::from(value()?)),
+                "--levels-dir" => parsed.levels_dir = Some(PathBuf::from(value()?)),
+                "--fullscreen" => parsed.fullscreen = true,
+                "--scale" => {
+                    let scale = value()?;
+                    match scale.parse() {
+                        Ok(n) if n > 0 => parsed.scale = Some(n),
+                        _ => return Err(format!("--scale {:?} is not a size", scale)),
+                    }
+                }
+                "--mute" => parsed.mute = true,
+                _ => return Err(format!("unknown argument {:?}", arg)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Reads the program's arguments, quitting with the usage if they're wrong
+    pub fn from_env() -> Args {
+        Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        })
+    }
+
+    /// Folder the levels are read from
+    pub fn pack(&self) -> PathBuf {
+        match (&self.levels_dir, &self.pack) {
+            (Some(dir), Some(pack)) => dir.join(pack),
+            (Some(dir), None) => dir.clone(),
+            (None, Some(pack)) => pack.clone(),
+            (None, None) => PathBuf::from("levels"),
+        }
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        config.fullscreen |= self.fullscreen;
+        config.muted |= self.mute;
+        if let Some(scale) = self.scale {
+            config.scale = scale.min(MAX_SCALE);
+        }
+    }
+
+    /// Puts the `saved` value back into every setting the arguments
+    /// override, so they only last for this run
+    pub fn unapply(&self, config: &mut Config, saved: &Config) {
+        if self.fullscreen {
+            config.fullscreen = saved.fullscreen;
+        }
+        if self.mute {
+            config.muted = saved.muted;
+        }
+        if self.scale.is_some() {
+            config.scale = saved.scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn arguments_override_the_settings() {
+        let args = parse("--level 5-3 --scale 4 --mute --levels-dir packs --pack hard").unwrap();
+        assert_eq!(args.level.as_deref(), Some("5-3"));
+        assert_eq!(args.pack(), PathBuf::from("packs/hard"));
+
+        let mut config = Config::default();
+        args.apply(&mut config);
+        assert_eq!(
+            (config.scale, config.muted, config.fullscreen),
+            (4, true, false)
+        );
+        config.music_volume = 0.5;
+        args.unapply(&mut config, &Config::default());
+        assert_eq!(
+            config,
+            Config {
+                music_volume: 0.5,
+                ..Config::default()
+            }
+        );
+
+        assert_eq!(parse("").unwrap().pack(), PathBuf::from("levels"));
+        assert!(parse("--scale 0").is_err());
+        assert!(parse("--level").is_err());
+        assert!(parse("--levle 5-3").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use macroquad::{
+    audio::{load_sound_from_bytes, Sound},
+    file::{load_file, FileError},
+    miniquad::fs,
+    prelude::*,
+};
+use std::fmt;
+
+use super::sound::ogg_seconds;
+
+/// An asset that couldn't be found or decoded
+#[derive(Debug)]
+pub struct AssetError {
+    pub path: String,
+    pub reason: String,
+}
+
+impl AssetError {
+    fn from_file_error(path: &str, error: FileError) -> Self {
+        let reason = match error.kind {
+            fs::Error::IOError(e) => e.to_string(),
+            other => format!("{:?}", other),
+        };
+        AssetError {
+            path: path.to_owned(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot load asset \"{}\": {}", self.path, self.reason)
+    }
+}
+
+/// Loads assets one by one, drawing the loading screen in between
+pub struct Loader {
+    loaded: usize,
+    total: usize,
+}
+
+impl Loader {
+    pub fn new(total: usize) -> Self {
+        Loader { loaded: 0, total }
+    }
+
+    async fn load_bytes(&mut self, path: &str) -> Result<Vec<u8>, AssetError> {
+        clear_background(BLACK);
+        let (w, h) = (screen_width(), screen_height());
+        let progress = self.loaded as f32 / self.total.max(1) as f32;
+        draw_text("LOADING...", w / 2. - 60., h / 2. - 20., 30., GREEN);
+        draw_rectangle_lines(w / 4., h / 2., w / 2., 12., 2., GREEN);
+        draw_rectangle(w / 4., h / 2., w / 2. * progress, 12., GREEN);
+        draw_text(path, w / 4., h / 2. + 36., 20., DARKGREEN);
+        next_frame().await;
+
+        self.loaded += 1;
+        load_file(path)
+            .await
+            .map_err(|e| AssetError::from_file_error(path, e))
+    }
+
+    pub async fn text(&mut self, path: &str) -> Result<String, AssetError> {
+        let bytes = self.load_bytes(path).await?;
+        String::from_utf8(bytes).map_err(|e| AssetError {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        })
+    }
+
+    pub async fn texture(&mut self, path: &str) -> Result<Texture2D, AssetError> {
+        let bytes = self.load_bytes(path).await?;
+        let texture = Texture2D::from_file_with_format(&bytes, None);
+        texture.set_filter(FilterMode::Nearest);
+        Ok(texture)
+    }
+
+    pub async fn font(&mut self, path: &str) -> Result<Font, AssetError> {
+        let bytes = self.load_bytes(path).await?;
+        load_ttf_font_from_bytes(&bytes).map_err(|e| AssetError {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// A sound, along with how many seconds it lasts
+    pub async fn sound(&mut self, path: &str) -> Result<(Sound, f32), AssetError> {
+        let bytes = self.load_bytes(path).await?;
+        let sound = load_sound_from_bytes(&bytes)
+            .await
+            .map_err(|e| AssetError::from_file_error(path, e))?;
+        Ok((sound, ogg_seconds(&bytes)))
+    }
+}
+
+/// Every image and font the game uses, loaded once at startup
+pub struct Assets {
+    pub font: Font,
+    pub tiles: Texture2D, // single image that contains all the tiles
+    pub scoreboard: Texture2D,
+    pub brick_decoration: Texture2D,
+    pub menu_bg: Texture2D,
+}
+
+impl Assets {
+    /// Number of files loaded by `Assets::load`
+    pub const COUNT: usize = 5;
+
+    pub async fn load(loader: &mut Loader) -> Result<Self, AssetError> {
+        Ok(Assets {
+            font: loader.font("Nintendo-NES-Font.ttf").await?,
+            tiles: loader.texture("img/tiles.png").await?,
+            scoreboard: loader.texture("img/scoreboard.png").await?,
+            brick_decoration: loader.texture("img/brick_decoration.png").await?,
+            menu_bg: loader.texture("img/menu_bg.png").await?,
+        })
+    }
+
+    /// Placeholder handles, for running the game logic without drawing it
+    pub fn empty() -> Self {
+        Assets {
+            font: Font::default(),
+            tiles: Texture2D::empty(),
+            scoreboard: Texture2D::empty(),
+            brick_decoration: Texture2D::empty(),
+            menu_bg: Texture2D::empty(),
+        }
+    }
+}
+
+/// Shows a fatal error until a key is pressed
+pub async fn show_error(error: &dyn fmt::Display) {
+    eprintln!("{}", error);
+    loop {
+        clear_background(BLACK);
+        draw_text(&error.to_string(), 10., 30., 20., RED);
+        draw_text("Press any key to exit", 10., 60., 20., LIGHTGRAY);
+        if get_last_key_pressed().is_some() {
+            break;
+        }
+        next_frame().await;
+    }
+}
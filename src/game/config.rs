@@ -0,0 +1,333 @@
+use std::{fs, path::Path, str::FromStr};
+
+use super::{
+    input::{key_from_name, key_name, Action, KeyBindings},
+    playing_state::TILE_HEIGHT,
+};
+
+/// Where the settings are kept between games
+pub const CONFIG_FILE: &str = "puzznic.cfg";
+
+/// Largest window, in times the original 320x200
+pub const MAX_SCALE: u32 = 6;
+
+/// Player preferences that change how the game plays
+#[derive(Clone, PartialEq, Debug)]
+pub struct Config {
+    /// Holding grab slides the tile as many cells as wanted, instead of one
+    pub continuous_drag: bool,
+    /// Seconds a continuous drag asked for mid-animation waits for the board
+    /// to settle, zero carries drags out right away
+    pub input_buffer: f64,
+    /// Seconds a cursor key has to be held before it starts repeating
+    pub repeat_delay: f64,
+    /// Seconds between repeats of a held cursor key
+    pub repeat_interval: f64,
+    /// Which of the `physics` rule sets is played
+    pub game_speed: GameSpeed,
+    /// Physics of every game speed, in the order of `GameSpeed::ALL`
+    pub physics: [Physics; 3],
+    /// Volume of the music, from 0 to 1
+    pub music_volume: f32,
+    /// Volume of the sound effects, from 0 to 1
+    pub sfx_volume: f32,
+    /// Silences both music and sound effects, keeping their volumes
+    pub muted: bool,
+    /// Whether the game takes the whole screen
+    pub fullscreen: bool,
+    /// Size of the window, in times the original 320x200
+    pub scale: u32,
+    /// Key played with for each action
+    pub keys: KeyBindings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            continuous_drag: false,
+            input_buffer: 0.5,
+            repeat_delay: 0.25,
+            repeat_interval: 0.06,
+            game_speed: GameSpeed::Classic,
+            physics: [
+                Physics::preset(GameSpeed::Classic),
+                Physics::preset(GameSpeed::Fast),
+                Physics::preset(GameSpeed::Relaxed),
+            ],
+            music_volume: 0.8,
+            sfx_volume: 1.,
+            muted: false,
+            fullscreen: false,
+            scale: 3,
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Physics of the chosen game speed
+    pub fn physics(&self) -> Physics {
+        self.physics[self.game_speed as usize]
+    }
+
+    /// Reads the settings saved in `path`, the defaults standing in for
+    /// anything missing or that can't be read
+    pub fn load(path: &Path) -> Config {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::from_text(&text),
+            Err(e) => {
+                println!(
+                    "Using default settings, cannot read {}: {}",
+                    path.display(),
+                    e
+                );
+                Config::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        if let Err(e) = fs::write(path, self.to_text()) {
+            println!("Cannot save settings to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Reads `key = value` lines, ignoring blank lines and `#` comments
+    pub fn from_text(text: &str) -> Config {
+        let mut config = Config::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let read = match line.split_once('=') {
+                Some((key, value)) => config.set(key.trim(), value.trim()),
+                None => false,
+            };
+            if !read {
+                println!("Ignoring setting {:?}", line);
+            }
+        }
+        config
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            "# Puzznic settings".to_string(),
+            format!("continuous_drag = {}", self.continuous_drag),
+            format!("input_buffer = {}", self.input_buffer),
+            format!("repeat_delay = {}", self.repeat_delay),
+            format!("repeat_interval = {}", self.repeat_interval),
+            format!("game_speed = {}", self.game_speed.name().to_lowercase()),
+            format!("music_volume = {}", self.music_volume),
+            format!("sfx_volume = {}", self.sfx_volume),
+            format!("muted = {}", self.muted),
+            format!("fullscreen = {}", self.fullscreen),
+            format!("scale = {}", self.scale),
+        ];
+        for action in Action::ALL.iter() {
+            let name = action.name().to_lowercase();
+            lines.push(format!(
+                "key.{} = {}",
+                name,
+                key_name(self.keys.key(*action))
+            ));
+        }
+        for (speed, physics) in GameSpeed::ALL.iter().zip(self.physics.iter()) {
+            let name = speed.name().to_lowercase();
+            lines.push(format!("{}.gravity = {}", name, physics.gravity));
+            lines.push(format!(
+                "{}.max_fall_speed = {}",
+                name, physics.max_fall_speed
+            ));
+            lines.push(format!(
+                "{}.elevator_speed = {}",
+                name, physics.elevator_speed
+            ));
+            lines.push(format!("{}.fade_frames = {}", name, physics.fade_frames));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Sets one setting from the text of its value, false if it can't be done
+    fn set(&mut self, key: &str, value: &str) -> bool {
+        fn parse<T: FromStr>(field: &mut T, value: &str) -> bool {
+            value.parse().map(|v| *field = v).is_ok()
+        }
+        /// A speed above zero, and below a tile a frame so nothing jumps
+        /// over another tile
+        fn parse_speed(field: &mut f32, value: &str) -> bool {
+            match value.parse() {
+                Ok(v) if v > 0. && v <= TILE_HEIGHT => {
+                    *field = v;
+                    true
+                }
+                _ => false,
+            }
+        }
+        match key {
+            "continuous_drag" => parse(&mut self.continuous_drag, value),
+            "input_buffer" => parse(&mut self.input_buffer, value),
+            "repeat_delay" => parse(&mut self.repeat_delay, value),
+            "repeat_interval" => parse(&mut self.repeat_interval, value),
+            "game_speed" => match GameSpeed::from_name(value) {
+                Some(speed) => {
+                    self.game_speed = speed;
+                    true
+                }
+                None => false,
+            },
+            "music_volume" => parse(&mut self.music_volume, value),
+            "sfx_volume" => parse(&mut self.sfx_volume, value),
+            "muted" => parse(&mut self.muted, value),
+            "fullscreen" => parse(&mut self.fullscreen, value),
+            "scale" => {
+                let read = parse(&mut self.scale, value);
+                self.scale = self.scale.clamp(1, MAX_SCALE);
+                read
+            }
+            _ if key.starts_with("key.") => {
+                let action = Action::ALL
+                    .iter()
+                    .find(|a| a.name().eq_ignore_ascii_case(&key["key.".len()..]));
+                match (action, key_from_name(value)) {
+                    (Some(action), Some(key)) => {
+                        self.keys.bind(*action, key);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => {
+                let (speed, field) = match key.split_once('.') {
+                    Some((speed, field)) => (GameSpeed::from_name(speed), field),
+                    None => return false,
+                };
+                let physics = match speed {
+                    Some(speed) => &mut self.physics[speed as usize],
+                    None => return false,
+                };
+                match field {
+                    "gravity" => parse_speed(&mut physics.gravity, value),
+                    "max_fall_speed" => parse_speed(&mut physics.max_fall_speed, value),
+                    "elevator_speed" => parse_speed(&mut physics.elevator_speed, value),
+                    "fade_frames" => match value.parse() {
+                        Ok(frames) if frames > 0 => {
+                            physics.fade_frames = frames;
+                            true
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Game speeds to choose from, each with its own `Physics`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GameSpeed {
+    Classic,
+    Fast,
+    Relaxed,
+}
+
+impl GameSpeed {
+    pub const ALL: [GameSpeed; 3] = [GameSpeed::Classic, GameSpeed::Fast, GameSpeed::Relaxed];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameSpeed::Classic => "CLASSIC",
+            GameSpeed::Fast => "FAST",
+            GameSpeed::Relaxed => "RELAXED",
+        }
+    }
+
+    /// Looks up a game speed by its name, in any case
+    pub fn from_name(name: &str) -> Option<GameSpeed> {
+        GameSpeed::ALL
+            .iter()
+            .copied()
+            .find(|s| s.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// How tiles and elevators move, in pixels and frames
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Physics {
+    /// Speed a falling tile gains every frame
+    pub gravity: f32,
+    /// Fastest a tile can fall
+    pub max_fall_speed: f32,
+    /// Speed of elevators that don't set one in the level file
+    pub elevator_speed: f32,
+    /// Frames matched tiles take to fade out
+    pub fade_frames: u32,
+}
+
+impl Physics {
+    pub fn preset(speed: GameSpeed) -> Physics {
+        match speed {
+            // Falls at a steady pixel per frame, as the original did
+            GameSpeed::Classic => Physics {
+                gravity: 1.,
+                max_fall_speed: 1.,
+                elevator_speed: 1.,
+                fade_frames: 50,
+            },
+            GameSpeed::Fast => Physics {
+                gravity: 0.25,
+                max_fall_speed: 4.,
+                elevator_speed: 2.,
+                fade_frames: 25,
+            },
+            GameSpeed::Relaxed => Physics {
+                gravity: 0.05,
+                max_fall_speed: 1.,
+                elevator_speed: 0.5,
+                fade_frames: 80,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::input::KeyCode;
+
+    #[test]
+    fn settings_survive_a_save() {
+        let mut config = Config {
+            continuous_drag: true,
+            game_speed: GameSpeed::Relaxed,
+            music_volume: 0.25,
+            muted: true,
+            scale: 4,
+            ..Config::default()
+        };
+        config.keys.bind(Action::Grab, KeyCode::LeftControl);
+        config.physics[GameSpeed::Fast as usize].gravity = 0.5;
+        assert_eq!(Config::from_text(&config.to_text()), config);
+    }
+
+    #[test]
+    fn unreadable_settings_keep_their_defaults() {
+        let config =
+            Config::from_text("sfx_volume = loud\ngame_speed = warp\nfast.jump = 2\nmuted");
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn physics_that_cannot_be_played_are_ignored() {
+        let text = "classic.gravity = -1\nclassic.max_fall_speed = 0\n\
+            fast.elevator_speed = 100\nfast.fade_frames = 0\nrelaxed.gravity = NaN";
+        assert_eq!(Config::from_text(text), Config::default());
+    }
+
+    #[test]
+    fn window_scale_is_kept_in_range() {
+        assert_eq!(Config::from_text("scale = 0").scale, 1);
+        assert_eq!(Config::from_text("scale = 40").scale, MAX_SCALE);
+    }
+}
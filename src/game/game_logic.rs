@@ -1,42 +1,78 @@
-use super::{playing_state::*, sound::{Mixer, self}, tile::Tile};
+use super::{assets::Assets, config::{Config, GameSpeed, Physics}, grid::Grid, input::{InputBuffer, KeyBindings, KeyRepeat}, menu::Menu, playing_state::*, results_state::LevelResults, sound::{Mixer, self}, tile::Tile, tile_kind::TileKind};
 use crate::game::tile::TileChange;
 use macroquad::prelude::*;
-use std::{collections::HashMap};
 
 pub struct PlayingState {
     pub map: Vec<Tile>,
+    pub grid: Grid,             // spatial index of the map, rebuilt every time it changes
     pub blanks: Vec<Tile>,      // empty spaces to draw the background
     pub texture_map: Texture2D, // single image that contains all the tiles
     pub scoreboard_texture: Texture2D,
     pub brick_decoration: Texture2D,
-    pub tile_info: HashMap<char, u32>, // image offset of each tile in the main image
     pub dimensions: (usize, usize),    // map dimensions,
     pub level: usize,
+    pub name: String,
+    pub music: sound::Sounds,
     pub time: u16,
     pub paused: bool,
+    pub pause_menu: Menu<PauseChoice>, // shown while paused
     pub exit_intent: bool,
     pub offset_x: f32,
     pub offset_y: f32,
     pub font: Font,
     pub player: Player,
     pub score: u32,
-    pub time_elpsed: u32,
+    pub time_elpsed: f64, // seconds played, leaving out the intro and pauses
+    pub moves: u32,  // tiles pushed
+    pub clears: u32, // tiles matched
     pub fading_out: bool,
     pub dragging: bool,
-    pub dragging_step: u8
+    pub dragging_step: u8,
+    pub continuous_drag: bool, // see `Config::continuous_drag`
+    pub physics: Physics,      // how fast things fall, fade and ride
+    pub input_buffer: InputBuffer, // drags waiting for the board to settle
+    pub keys: KeyBindings,
+    pub key_repeat: [KeyRepeat; 4], // auto-repeat of each of the `CURSOR_ACTIONS`
+    pub intro_end: Option<f64>, // when the level intro is over, while it plays
+    pub preview: Option<Preview>, // landings shown while grabbing, kept until the board changes
 }
 
 pub struct LevelInfo {
     pub tiles: Vec<Tile>,
     pub blanks: Vec<Tile>,
+    pub start: (usize, usize), // cell the cursor starts on
     pub width: usize,
     pub height: usize,
     pub offset_x: f32,
     pub offset_y: f32,
     pub level: usize,
+    pub world: usize,
+    pub name: String,   // as shown to the player, such as 3-2
+    pub password: String, // lets the player start from this level
+    pub music: sound::Sounds, // track played throughout the level
     pub time: u16
 }
 
+/// Where a dragged tile would come to rest, see `PlayingState::preview_drag`
+pub struct Landing {
+    pub kind: &'static TileKind,
+    pub position: Vec2,
+    pub matches: Vec<Vec2>, // tiles that would vanish once it lands
+}
+
+/// Landings of a grab, worked out once for the board it was made on
+pub struct Preview {
+    pub cursor: (usize, usize),
+    pub board: Vec<Vec2>, // position of every tile when the preview was made
+    pub landings: Vec<Landing>,
+}
+
+/// How far apart, in pixels, two stacked tiles may drift and still count as touching
+const STACK_TOLERANCE: f32 = 2.;
+
+/// Longest a drag is played out for when previewing it
+const MAX_PREVIEW_FRAMES: usize = 600;
+
 /// AABB collision detection, returns true if collision found
 fn check_collision_perfect(t1: &Tile, coordinates: &Vec2) -> bool {
      (t1.position.x - coordinates.x).abs() < TILE_WIDTH
@@ -44,104 +80,243 @@ fn check_collision_perfect(t1: &Tile, coordinates: &Vec2) -> bool {
 }
 
 impl PlayingState {
-    pub fn get_tile_texture_params(&self, c: char) -> DrawTextureParams {
-        let offset = *self
-            .tile_info
-            .get(&c)
-            .expect(format!("cannot find tile {}", c).as_str()) as f32;
+    pub fn get_tile_texture_params(&self, kind: &TileKind) -> DrawTextureParams {
+        let offset = kind.sprite as f32;
         // let ratio = screen_width() / screen_height();
-        let params = DrawTextureParams {
-            dest_size: Some(Vec2::new(TILE_WIDTH * 1., TILE_HEIGHT * 1. as f32)),
-            source: Some(Rect::new(offset, 0., TILE_WIDTH as f32, TILE_HEIGHT as f32)),
+        DrawTextureParams {
+            dest_size: Some(Vec2::new(TILE_WIDTH * 1., TILE_HEIGHT * 1.)),
+            source: Some(Rect::new(offset, 0., TILE_WIDTH, TILE_HEIGHT)),
             rotation: 0.,
             pivot: None,
             flip_x: false,
             flip_y: false,
-        };
-
-        params
+        }
     }
 
     // check if tile collides with any other tile of the map
+    // if several do, the one that comes first in the map wins
     fn check_collision(&self, t1: &Tile, _map: &Vec<Tile>, coordinates: &Vec2) -> Option<usize> {
-        for (index, tile) in self.map.iter().enumerate() {
-            if tile.id != t1.id {
-                if check_collision_perfect(tile, &coordinates) {
-                    return Some(index);
-                }
+        let mut found: Option<usize> = None;
+        self.grid.for_each_candidate(coordinates, |index| {
+            let tile = &self.map[index];
+            if tile.id != t1.id
+                && check_collision_perfect(tile, coordinates)
+                && found.map_or(true, |f| index < f)
+            {
+                found = Some(index);
             }
-        }
-        None
+        });
+        found
     }
 
     pub fn get_tile_at(&self, x: usize, y: usize) -> Option<usize> {
-        for (index, tile) in self.map.iter().enumerate() {
-            if (tile.position.x / TILE_WIDTH) as usize == x
-                && (tile.position.y / TILE_HEIGHT) as usize == y
-            {
-                return Some(index);
-            }
-        }
-        None
+        self.grid
+            .at(x, y)
+            .iter()
+            .copied()
+            .filter(|&index| {
+                let tile = &self.map[index];
+                (tile.position.x / TILE_WIDTH) as usize == x
+                    && (tile.position.y / TILE_HEIGHT) as usize == y
+            })
+            .min()
     }
 
-    pub async fn set_level(&mut self, info: LevelInfo) {
+    pub fn set_level(&mut self, info: LevelInfo) {
         self.map = info.tiles;
         self.blanks = info.blanks;
         self.dimensions = (info.width, info.height);
-        self.player.position = (info.width / 2 - 1, info.height / 2);
+        self.grid = Grid::new(info.width, info.height);
+        self.grid.rebuild(&self.map);
+        self.player.position = info.start;
         self.offset_x = info.offset_x;
         self.offset_y = info.offset_y;
         self.time = info.time;
         self.level = info.level;
+        self.name = info.name;
+        self.music = info.music;
+
+    }
+    /// Picks up the settings that affect gameplay
+    pub fn apply_config(&mut self, config: &Config) {
+        self.continuous_drag = config.continuous_drag;
+        self.input_buffer.set_window(config.input_buffer);
+        for key in self.key_repeat.iter_mut() {
+            key.delay = config.repeat_delay;
+            key.interval = config.repeat_interval;
+        }
+        self.keys = config.keys;
+        self.physics = config.physics();
+        let elevator_speed = self.physics.elevator_speed;
+        for tile in self.map.iter_mut() {
+            if let Some(path) = &tile.path {
+                let speed = path.speed.unwrap_or(elevator_speed);
+                tile.velocity = tile.velocity.normalize_or_zero() * speed;
+            }
+        }
+    }
+
+    /// Counts `seconds` towards the time used, unless the level is still
+    /// being introduced, paused or about to be left
+    pub fn count_time(&mut self, seconds: f64) {
+        if self.intro_end.is_none() && !self.paused && !self.exit_intent {
+            self.time_elpsed += seconds;
+        }
+    }
+
+    /// Whole seconds of the level's time that haven't been used yet
+    pub fn time_left(&self) -> u16 {
+        (self.time as f64 - self.time_elpsed).max(0.) as u16
+    }
 
+    /// How the level went, for the results screen
+    pub fn results(&self) -> LevelResults {
+        LevelResults {
+            level: self.level,
+            name: self.name.clone(),
+            time_used: self.time_elpsed as u32,
+            time_left: self.time_left(),
+            moves: self.moves,
+            clears: self.clears,
+        }
     }
-    pub async fn new() -> Self {
-        let texture_map = load_texture("img/tiles.png").await.unwrap();
-        //   set_texture_filter(texture_map, FilterMode::Nearest);
-        texture_map.set_filter(FilterMode::Nearest);
-        let font = load_ttf_font("Nintendo-NES-Font.ttf").await.unwrap();
 
-        let mut tile_info = HashMap::new();
-        tile_info.insert('G', 0u32);
-        tile_info.insert('X', 16u32);
-        tile_info.insert('E', 32u32);
-        tile_info.insert('B', 48u32);
-        tile_info.insert('P', 64u32);
-        tile_info.insert('C', 80u32);
-        tile_info.insert('D', 96u32);
-        tile_info.insert('?', 112u32);
-        tile_info.insert('~', 112u32);
-        tile_info.insert('|', 112u32);
-        tile_info.insert('-', 128u32);
+    /// Whether nothing is falling, about to fall, about to match or fading out.
+    /// Tiles riding an elevator count as settled, as they may never stop moving.
+    pub fn is_stable(&self) -> bool {
+        !self.fading_out
+            && self.find_matches().is_empty()
+            && self.map.iter().all(|t| {
+                if !t.kind.movable || t.riding {
+                    return true;
+                }
+                let below = t.position + Vec2::new(0., SPEED);
+                t.velocity == Vec2::ZERO
+                    && t.dragging_direction.is_none()
+                    && self.check_collision(t, &self.map, &below).is_some()
+            })
+    }
+
+    /// Where the tile under the cursor would come to rest if dragged one cell
+    /// to the left or right, found by playing the drag out on a copy of the board
+    pub fn preview_drag(&self, direction: Direction) -> Option<Landing> {
+        let (x, y) = self.player.position;
+        let new_x = match direction {
+            Direction::Left => x.checked_sub(1)?,
+            Direction::Right => x + 1,
+            _ => return None,
+        };
+        let index = self.get_tile_at(x, y)?;
+        let tile = &self.map[index];
+        if new_x >= self.dimensions.0
+            || !tile.kind.movable
+            || tile.looping
+            || self.get_tile_at(new_x, y).is_some()
+        {
+            return None;
+        }
 
-        let score_texture = load_texture("img/scoreboard.png").await.unwrap();
-        score_texture.set_filter(FilterMode::Nearest);
+        let mut board = self.copy_board();
+        board.player.position = (new_x, y);
+        board.map[index].dragging_direction = Some(direction);
+        let mut matches = vec![];
+        for _ in 0..MAX_PREVIEW_FRAMES {
+            board.update_tiles();
+            if board.fading_out {
+                matches = board
+                    .map
+                    .iter()
+                    .filter(|t| t.fade_step > 0)
+                    .map(|t| t.position)
+                    .collect();
+                break;
+            }
+            if board.is_stable() {
+                break;
+            }
+        }
+        let tile = board.map.iter().find(|t| t.id == tile.id)?;
+        Some(Landing {
+            kind: tile.kind,
+            position: tile.position,
+            matches,
+        })
+    }
 
-        let brick_texture = load_texture("img/brick_decoration.png").await.unwrap();
-        brick_texture.set_filter(FilterMode::Nearest);
+    /// Works out the landings of a drag either way from the cursor, unless
+    /// neither the cursor nor the board has changed since the last time
+    pub fn update_preview(&mut self) {
+        let board: Vec<Vec2> = self.map.iter().map(|t| t.position).collect();
+        let cursor = self.player.position;
+        let fresh = matches!(&self.preview, Some(p) if p.cursor == cursor && p.board == board);
+        if !fresh {
+            let landings = [Direction::Left, Direction::Right]
+                .iter()
+                .filter_map(|direction| self.preview_drag(direction.clone()))
+                .collect();
+            self.preview = Some(Preview {
+                cursor,
+                board,
+                landings,
+            });
+        }
+    }
 
+    /// The tiles and the rules they move by, without anything drawn or heard,
+    /// to play moves out on
+    fn copy_board(&self) -> PlayingState {
+        PlayingState {
+            map: self.map.clone(),
+            grid: self.grid.clone(),
+            dimensions: self.dimensions,
+            player: self.player.clone(),
+            fading_out: self.fading_out,
+            dragging: self.dragging,
+            continuous_drag: self.continuous_drag,
+            physics: self.physics,
+            ..PlayingState::new(&Assets::empty())
+        }
+    }
+
+    pub fn new(assets: &Assets) -> Self {
         PlayingState {
             map: vec![],
+            grid: Grid::new(0, 0),
             blanks: vec![],
-            texture_map,
+            texture_map: assets.tiles,
             paused: false,
+            pause_menu: pause_menu(),
             exit_intent: false,
-            font,
-            tile_info,
+            font: assets.font,
             player: Player { position: (0, 0) },
             dimensions: (0, 0),
             dragging: false,
             score: 0,
-            time_elpsed: 0,
+            time_elpsed: 0.,
+            moves: 0,
+            clears: 0,
             fading_out: false,
-            scoreboard_texture: score_texture,
-            brick_decoration: brick_texture,
+            scoreboard_texture: assets.scoreboard,
+            brick_decoration: assets.brick_decoration,
             offset_x: 0.0,
             offset_y: 0.0,
             level: 0,
             time: 0,
-            dragging_step: 0
+            dragging_step: 0,
+            continuous_drag: false,
+            physics: Physics::preset(GameSpeed::Classic),
+            input_buffer: InputBuffer::new(0.),
+            keys: KeyBindings::default(),
+            key_repeat: [
+                KeyRepeat::new(0., 0.),
+                KeyRepeat::new(0., 0.),
+                KeyRepeat::new(0., 0.),
+                KeyRepeat::new(0., 0.),
+            ],
+            intro_end: None,
+            preview: None,
+            name: String::new(),
+            music: sound::Sounds::Music1,
         }
     }
 
@@ -149,21 +324,17 @@ impl PlayingState {
         let mut new_x: usize = self.player.position.0;
         let mut new_y: usize = self.player.position.1;
 
-        // mixer.play_sound(sound::Sound::MOVE);
+        // mixer.play_sound(sound::Sound::Move);
         // let mut tile_state = TileState::NONE;
         match direction {
             Direction::Left => {
-                if new_x > 0 {
-                    new_x -= 1;
-                }
+                new_x = new_x.saturating_sub(1);
             }
             Direction::Right => {
                 new_x = usize::min(self.dimensions.0 - 1, new_x + 1);
             }
             Direction::Up => {
-                if new_y > 0 {
-                     new_y -= 1;
-                }
+                new_y = new_y.saturating_sub(1);
             }
             Direction::Down => new_y = usize::min(self.dimensions.1 - 1, new_y + 1),
             Direction::None => {}
@@ -173,15 +344,24 @@ impl PlayingState {
         // let tile = self.map.get_mut(index).unwrap();
 
         if self.dragging {
-            mixer.play_sound(sound::Sounds::MOVE).await;
+            mixer.play_sound(sound::Sounds::Move).await;
             if let Some(index) = self.get_tile_at(self.player.position.0, self.player.position.1) {
                 let index2 = self.get_tile_at(new_x, new_y);
+                let continuous_drag = self.continuous_drag;
                 let tile_underneath = self.map.get_mut(index).unwrap();
-                if tile_underneath.is_playable()
-                    && tile_underneath.looping == false
-                    && index2.is_none()
-                {
-                    tile_underneath.dragging_direction = Some(direction);
+                if tile_underneath.kind.movable && !tile_underneath.looping {
+                    // a falling tile has slipped out of the player's hands
+                    let falling = tile_underneath.velocity != Vec2::ZERO && !tile_underneath.riding;
+                    if index2.is_none() && !(continuous_drag && falling) {
+                        tile_underneath.dragging_direction = Some(direction);
+                        self.moves += 1;
+                    } else if continuous_drag
+                        && !falling
+                        && (direction == Direction::Left || direction == Direction::Right)
+                    {
+                        // keep holding the tile rather than letting go of it
+                        return;
+                    }
                 }
             }
         }
@@ -191,118 +371,249 @@ impl PlayingState {
 
     fn new_handle_dragging(&self, tile: &Tile) -> Option<TileChange> {
         if let Some(direction) = &tile.dragging_direction {
-            match direction {
-                Direction::Left | Direction::Right => {
-                    let coordinates = Vec2::new(
-                        (self.player.position.0) as f32 * TILE_HEIGHT,
-                        (self.player.position.1) as f32 * TILE_HEIGHT,
-                    );
-                    let collision = self.check_collision(&tile, &self.map, &coordinates);
-                    if collision.is_none() {
-                        println!("Foo");
-                        return Some(TileChange::Jump(Vec2::new(
-                            (self.player.position.0) as f32 * TILE_HEIGHT,
-                            (self.player.position.1) as f32 * TILE_HEIGHT,
-                        )));
-                    } else {
-                        return Some(TileChange::Stop);
-                    }
-                }
-                Direction::None => return None,
-                Direction::Up => return None,
-                Direction::Down => return None,
+            let dx = match direction {
+                Direction::Left => -1.,
+                Direction::Right => 1.,
+                Direction::None | Direction::Up | Direction::Down => return None,
+            };
+            // A tile riding an elevator is seldom on the grid, drop it in the nearest cell
+            let coordinates = Vec2::new(
+                ((tile.position.x / TILE_WIDTH).round() + dx) * TILE_WIDTH,
+                (tile.position.y / TILE_HEIGHT).round() * TILE_HEIGHT,
+            );
+            if self.check_collision(tile, &self.map, &coordinates).is_none() {
+                return Some(TileChange::Jump(coordinates));
+            } else {
+                return Some(TileChange::Stop);
             }
         }
         None
     }
 
-    fn can_push(&self, tile: &Tile, velocity: &Vec2) -> bool {
-        // A tile can be pushed/moved to a certan location
-        //  if it doesn't collide with anything on that location
-        // or, if it collides, that tile can be pushed as well.
-        if !tile.is_playable() {
-            return false;
-        }
-        let new_position = tile.position + *velocity;
-        if let Some(index) = self.check_collision(&tile, &self.map, &new_position) {
-            let collider = self.map.get(index).unwrap();
-            return self.can_push(&collider, velocity);
-        }
-        true
+    /// Whether a tile placed at `coordinates` would overlap any tile other
+    /// than the ones in `group`
+    fn collides_outside(&self, coordinates: &Vec2, group: &[usize]) -> bool {
+        let mut collides = false;
+        self.grid.for_each_candidate(coordinates, |index| {
+            collides |= !group.contains(&index)
+                && check_collision_perfect(&self.map[index], coordinates);
+        });
+        collides
     }
-    fn new_handle_collision(&self, tile: &Tile, map: &Vec<Tile>) -> Option<TileChange> {
-        // Find out the next theorical coordinates
-        let new_position = tile.position + tile.velocity;
-        if let Some(index) = self.check_collision(&tile, &map, &new_position) {
-            // The tiles is moving and has collided with some other tile
-            let collider = self.map.get(index).unwrap();
-            if tile.c == '|' || tile.c == '~' {
-                if self.can_push(&collider, &tile.velocity) && tile.velocity != Vec2::new(0., SPEED)
+
+    /// Every tile stacked on top of the elevator, paired with the index of
+    /// the tile it rests on
+    fn stack_on(&self, elevator: usize) -> Vec<(usize, usize)> {
+        let mut stack: Vec<(usize, usize)> = vec![];
+        let mut next = 0;
+        let mut support = elevator;
+        loop {
+            let below = &self.map[support];
+            let above = below.position - Vec2::new(0., TILE_HEIGHT);
+            self.grid.for_each_candidate(&above, |index| {
+                let t = &self.map[index];
+                if t.kind.movable
+                    && t.fade_step == 0
+                    && (t.position.x - below.position.x).abs() < TILE_WIDTH
+                    && (t.position.y - above.y).abs() <= STACK_TOLERANCE
+                    && !stack.iter().any(|(i, _)| *i == index)
                 {
-                    return None;
-                } else {
-                    debug!("Cannot push, Tile {} bounces with {}", tile.c, collider.c);
-                    return Some(TileChange::Bounce);
+                    stack.push((index, support));
                 }
-            } else if collider.riding {
-                return Some(TileChange::StartRiding(collider.velocity.clone()));
-            } else if tile.c != '|' && tile.c != '~' {
-                debug!("Tile {} stops", tile.c);
-                return Some(TileChange::Stop);
+            });
+            if next == stack.len() {
+                return stack;
             }
-        } else {
-            // The tile won't collide. If it is not moving, should it fall?
-            if tile.velocity == Vec2::ZERO {
-                let new_position = tile.position + Vec2::new(0., SPEED);
-                if let Some(index) = self.check_collision(&tile, &map, &new_position) {
-                    let tile_underneath = self.map.get(index).unwrap();
-                    if tile_underneath.c == '|' || tile_underneath.c == '~' {
-                        debug!("Tile {} starts riding", tile.c);
-                        return Some(TileChange::StartRiding(tile_underneath.velocity.clone()));
-                    } else {
-                        return Some(TileChange::Stop);
-                    }
-                } else {
-                    // There's nothing underneath, we should fall
-                    return Some(TileChange::Fall);
+            support = stack[next].0;
+            next += 1;
+        }
+    }
+
+    /// Moves every elevator along its path with the column of tiles stacked
+    /// on it. An elevator turns back, after its pause, at either end of its
+    /// range or when anything in the way can't be pushed. Elevators that
+    /// don't loop stop for good there instead.
+    /// Returns the changes, and which tiles are being carried.
+    fn handle_elevators(&self) -> (Vec<(usize, TileChange)>, Vec<bool>) {
+        let mut changes = vec![];
+        let mut carried = vec![false; self.map.len()];
+        for (index, elevator) in self.map.iter().enumerate() {
+            let path = match &elevator.path {
+                Some(path) if elevator.fade_step == 0 => path,
+                _ => continue,
+            };
+            if elevator.velocity == Vec2::ZERO && path.waiting == 0 {
+                // parked at the end of a one way trip, what's on it rests
+                // there like on any other floor
+                continue;
+            }
+            let stack = self.stack_on(index);
+            for (i, _) in stack.iter() {
+                carried[*i] = true;
+            }
+            if path.waiting > 0 {
+                changes.push((index, TileChange::Wait));
+                continue;
+            }
+
+            // How far it moves this frame, stopping short at the ends of its range
+            let mut delta = elevator.velocity;
+            let mut arrived = false;
+            if let Some(range) = path.range {
+                let travelled = (elevator.position - path.origin).dot(path.direction);
+                let next = travelled + elevator.velocity.dot(path.direction);
+                if next >= range {
+                    delta = path.direction * (range - travelled);
+                    arrived = true;
+                } else if next <= 0. {
+                    delta = path.direction * -travelled;
+                    arrived = true;
                 }
             }
+
+            let mut group: Vec<usize> = stack.iter().map(|(i, _)| *i).collect();
+            group.push(index);
+            let blocked = group
+                .iter()
+                .any(|&i| self.collides_outside(&(self.map[i].position + delta), &group));
+            if blocked {
+                debug!("Elevator {} is blocked", elevator.id);
+                changes.push((index, TileChange::Bounce));
+                continue;
+            }
+
+            if arrived {
+                changes.push((index, TileChange::Arrive(elevator.position + delta)));
+            } else {
+                changes.push((index, TileChange::Move));
+            }
+            for (i, support) in stack {
+                // keep the tile sitting right on top of whatever holds it
+                let t = &self.map[i];
+                let top = self.map[support].position.y + delta.y;
+                let position = Vec2::new(t.position.x + delta.x, top - TILE_HEIGHT);
+                changes.push((i, TileChange::Carry(position, elevator.velocity)));
+            }
         }
-        return Some(TileChange::Move);
+        (changes, carried)
     }
 
-    pub fn handle_matches(&self, tile: &Tile) -> Option<TileChange> {
-        for t in self.map.iter() {
-            if tile.id != t.id
-                && tile.c == t.c
-                && (((tile.position.x - t.position.x).abs() == TILE_WIDTH)
-                    && ((tile.position.y - t.position.y).abs() == 0.0)
-                    || ((tile.position.y - t.position.y).abs() == TILE_WIDTH)
-                        && ((tile.position.x - t.position.x).abs() == 0.0))
-            {
-                return Some(TileChange::FadeOut);
+    fn new_handle_collision(&self, tile: &Tile, map: &Vec<Tile>) -> Option<TileChange> {
+        if tile.riding {
+            // It was being carried, but nothing holds it any more
+            return Some(TileChange::RidingFlag(false));
+        }
+        // Falling tiles speed up until they reach the top speed
+        let speed = (tile.velocity.y + self.physics.gravity).min(self.physics.max_fall_speed);
+        let new_position = tile.position + Vec2::new(0., speed);
+        match self.check_collision(tile, map, &new_position) {
+            Some(index) if tile.velocity != Vec2::ZERO => {
+                // The tile was falling, drop it right on top of what it hit
+                debug!("Tile {} stops", tile.c);
+                let top = map[index].position.y - TILE_HEIGHT;
+                Some(TileChange::Land(Vec2::new(
+                    tile.position.x,
+                    top.max(tile.position.y),
+                )))
             }
+            Some(_) => Some(TileChange::Stop),
+            // There's nothing underneath, we should fall
+            None => Some(TileChange::Fall(speed)),
         }
+    }
 
-        None
+    /// Whether a tile sits still right on a grid cell, the only time it can match
+    fn is_resting(&self, tile: &Tile) -> bool {
+        let below = tile.position + Vec2::new(0., SPEED);
+        tile.velocity == Vec2::ZERO
+            && !tile.riding
+            && tile.dragging_direction.is_none()
+            && tile.fade_step == 0
+            && tile.position.x % TILE_WIDTH == 0.
+            && tile.position.y % TILE_HEIGHT == 0.
+            && self.check_collision(tile, &self.map, &below).is_some()
+    }
+
+    /// Resting tiles of the same kind right next to `tile`, if it rests itself
+    fn matching_neighbours(&self, tile: &Tile) -> Vec<usize> {
+        let mut found = vec![];
+        if !tile.kind.matchable || !self.is_resting(tile) {
+            return found;
+        }
+        let neighbours = [
+            Vec2::new(-TILE_WIDTH, 0.),
+            Vec2::new(TILE_WIDTH, 0.),
+            Vec2::new(0., -TILE_HEIGHT),
+            Vec2::new(0., TILE_HEIGHT),
+        ];
+        for offset in neighbours.iter() {
+            let position = tile.position + *offset;
+            self.grid.for_each_candidate(&position, |index| {
+                let t = &self.map[index];
+                if tile.id != t.id
+                    && tile.c == t.c
+                    && t.position == position
+                    && !found.contains(&index)
+                    && self.is_resting(t)
+                {
+                    found.push(index);
+                }
+            });
+        }
+        found
+    }
+
+    /// Every group of connected, resting tiles of the same kind. A group has
+    /// at least two tiles, and all of them clear together as a single match.
+    pub fn find_matches(&self) -> Vec<Vec<usize>> {
+        let mut matches = vec![];
+        let mut grouped = vec![false; self.map.len()];
+        for start in 0..self.map.len() {
+            if grouped[start] {
+                continue;
+            }
+            let mut group = vec![start];
+            let mut next = 0;
+            while next < group.len() {
+                for index in self.matching_neighbours(&self.map[group[next]]) {
+                    if !grouped[index] && !group.contains(&index) {
+                        group.push(index);
+                    }
+                }
+                next += 1;
+            }
+            if group.len() > 1 {
+                for &index in group.iter() {
+                    grouped[index] = true;
+                }
+                matches.push(group);
+            }
+        }
+        matches
     }
+
     /// Given a map, return all tiles that should change.
     /// That is, which cell (x,y) changes, and the Tile that should be placed there
     pub fn next_map(&self, map: &Vec<Tile>) -> Vec<(usize, TileChange)> {
         let mut changes: Vec<(usize, TileChange)> = vec![];
+        let mut carried = vec![false; map.len()];
+        if !self.fading_out {
+            let (elevator_changes, on_elevator) = self.handle_elevators();
+            changes.extend(elevator_changes);
+            carried = on_elevator;
+        }
 
         for (index, tile) in map.iter().enumerate() {
             if tile.is_playable() {
                 if tile.fade_step > 0 {
                     changes.push((index, TileChange::FadeOut));
-                } else if self.fading_out == false {
-                    if let Some(tc) = self.new_handle_collision(&tile, &map) {
+                } else if !self.fading_out {
+                    if tile.is_elevator() || carried[index] {
+                        // already moved by `handle_elevators`
+                    } else if let Some(tc) = self.new_handle_collision(tile, map) {
                         changes.push((index, tc));
                     }
-                    if let Some(tc) = self.new_handle_dragging(&tile) {
-                        changes.push((index, tc));
-                    }
-                    if let Some(tc) = self.handle_matches(&tile) {
+                    if let Some(tc) = self.new_handle_dragging(tile) {
                         changes.push((index, tc));
                     }
                 }
@@ -311,4 +622,499 @@ impl PlayingState {
 
         changes
     }
+
+    /// Moves the map one frame forward, returns the sounds it makes
+    pub fn update_tiles(&mut self) -> Vec<sound::Sounds> {
+        let mut changes = self.next_map(&self.map);
+        let matches = if self.fading_out {
+            vec![]
+        } else {
+            self.find_matches()
+        };
+        let mut sounds = vec![];
+        for group in matches.iter() {
+            changes.extend(group.iter().map(|&index| (index, TileChange::FadeOut)));
+            sounds.push(sound::Sounds::Match);
+            self.clears += group.len() as u32;
+        }
+        let mut drain: Vec<u32> = vec![];
+        self.fading_out = false;
+        for (index, tile_change) in &changes {
+            let t = self.map.get_mut(*index).unwrap();
+            match tile_change {
+                TileChange::Stop => {
+                    t.velocity = Vec2::ZERO;
+                    t.dragging_direction = None;
+                }
+                TileChange::Move => {
+                    t.position += t.velocity;
+                }
+                TileChange::Jump(position) => {
+                    if t.dragging_direction.is_some() {
+                        // the cursor follows the tile it's dragging
+                        self.player.position = (
+                            (position.x / TILE_WIDTH) as usize,
+                            (position.y / TILE_HEIGHT) as usize,
+                        );
+                    }
+                    t.position = *position;
+                    t.velocity = Vec2::ZERO;
+                    t.dragging_direction = None;
+                }
+                TileChange::Arrive(position) => {
+                    t.position = *position;
+                    t.turn_back();
+                    sounds.push(sound::Sounds::Elevator);
+                }
+                TileChange::Wait => {
+                    if let Some(path) = t.path.as_mut() {
+                        path.waiting -= 1;
+                    }
+                }
+                TileChange::Carry(position, velocity) => {
+                    t.position = *position;
+                    t.velocity = *velocity;
+                    t.riding = true;
+                }
+                TileChange::Bounce => {
+                    t.turn_back();
+                    sounds.push(sound::Sounds::Bounce);
+                }
+                TileChange::FadeOut => {
+                    t.fade_step += 1;
+                    self.fading_out = true;
+                    if t.fade_step >= self.physics.fade_frames {
+                        drain.push(t.id);
+                    }
+                }
+                TileChange::Fall(speed) => {
+                    t.velocity = Vec2::new(0., *speed);
+                    t.position += t.velocity;
+                    t.riding = false;
+                }
+                TileChange::Land(position) => {
+                    t.position = *position;
+                    t.velocity = Vec2::ZERO;
+                    t.dragging_direction = None;
+                    sounds.push(sound::Sounds::Land);
+                }
+                TileChange::RidingFlag(flag) => {
+                    t.riding = *flag;
+                    if !flag {
+                        t.velocity = Vec2::ZERO;
+                    }
+                }
+            }
+        }
+        self.map.retain(|e| !drain.contains(&e.id));
+        self.grid.rebuild(&self.map);
+        sounds
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{levels, states::block_on};
+    use std::{path::Path, time::Instant};
+
+    fn start_level(assets: &Assets, n: usize) -> PlayingState {
+        let mut level = PlayingState::new(assets);
+        let info = levels::load_level(Path::new("levels"), n).unwrap();
+        level.set_level(info);
+        level
+    }
+
+    /// A level built from rows of one character per cell
+    fn board(assets: &Assets, rows: &[&str]) -> PlayingState {
+        let mut tiles = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate().filter(|(_, c)| *c != ' ') {
+                let id = tiles.len() as u32 + 1;
+                tiles.push(Tile::new(id, c, x as f32 * TILE_WIDTH, y as f32 * TILE_HEIGHT));
+            }
+        }
+        let mut level = PlayingState::new(assets);
+        level.set_level(LevelInfo {
+            tiles,
+            blanks: vec![],
+            start: (rows[0].len() / 2 - 1, rows.len() / 2),
+            width: rows[0].len(),
+            height: rows.len(),
+            offset_x: 0.,
+            offset_y: 0.,
+            level: 0,
+            world: 1,
+            name: "1-1".to_string(),
+            password: "AAAA".to_string(),
+            music: sound::Sounds::Music1,
+            time: 0,
+        });
+        level
+    }
+
+    /// Drags the tile under the cursor one cell right
+    fn drag_right(level: &mut PlayingState, continuous: bool) {
+        level.dragging = true;
+        level.continuous_drag = continuous;
+        block_on(level.move_player(Direction::Right, &mut Mixer::empty()));
+    }
+
+    fn tile(level: &mut PlayingState, c: char) -> &mut Tile {
+        level.map.iter_mut().find(|t| t.c == c).unwrap()
+    }
+
+    #[test]
+    fn continuous_drag_holds_on_to_the_tile() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["-----", "-G  -", "-----"]);
+        level.player.position = (1, 1);
+        drag_right(&mut level, true);
+        assert_eq!(tile(&mut level, 'G').dragging_direction, Some(Direction::Right));
+        assert_eq!((level.player.position, level.moves), ((2, 1), 1));
+
+        // blocked, the cursor stays on the tile rather than letting go
+        let mut level = board(&assets, &["-----", "-GE -", "-----"]);
+        level.player.position = (1, 1);
+        drag_right(&mut level, true);
+        assert_eq!(tile(&mut level, 'G').dragging_direction, None);
+        assert_eq!((level.player.position, level.moves), ((1, 1), 0));
+
+        // one cell drags move the cursor on anyway
+        let mut level = board(&assets, &["-----", "-GE -", "-----"]);
+        level.player.position = (1, 1);
+        drag_right(&mut level, false);
+        assert_eq!(level.player.position, (2, 1));
+    }
+
+    #[test]
+    fn continuous_drag_lets_go_of_a_falling_tile() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["-----", "-G  -", "-   -", "-----"]);
+        level.player.position = (1, 1);
+        tile(&mut level, 'G').velocity = Vec2::new(0., 1.);
+        drag_right(&mut level, true);
+        assert_eq!(tile(&mut level, 'G').dragging_direction, None);
+        assert_eq!((level.player.position, level.moves), ((2, 1), 0));
+
+        // a one cell drag still pushes it
+        level.player.position = (1, 1);
+        drag_right(&mut level, false);
+        assert_eq!(tile(&mut level, 'G').dragging_direction, Some(Direction::Right));
+    }
+
+    #[test]
+    fn one_cell_drags_are_not_held_back_by_falling_tiles() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["------", "-G  E-", "--   -", "------"]);
+        level.apply_config(&Config::default());
+        level.player.position = (1, 1);
+        assert!(!level.is_stable());
+        let keys = |fired: [bool; 4], grab_down: bool, now: f64| MoveKeys {
+            fired,
+            grab_pressed: false,
+            grab_released: !grab_down,
+            grab_down,
+            now,
+        };
+        let mut mixer = Mixer::empty();
+        block_on(handle_move_keys(&mut level, &keys([false; 4], true, 0.), &mut mixer));
+        let right = [false, true, false, false];
+        block_on(handle_move_keys(&mut level, &keys(right, true, 0.1), &mut mixer));
+        assert_eq!(tile(&mut level, 'G').dragging_direction, Some(Direction::Right));
+        assert_eq!(level.moves, 1);
+
+        // the drag is carried out once grab is let go, long after the buffer
+        // would have given up on it
+        block_on(handle_move_keys(&mut level, &keys([false; 4], false, 2.), &mut mixer));
+        level.update_tiles();
+        assert_eq!(tile(&mut level, 'G').position.x, 2. * TILE_WIDTH);
+    }
+
+    #[test]
+    fn only_time_spent_playing_is_counted() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["-----", "-G G-", "-----"]);
+        level.time = 60;
+        level.intro_end = Some(3.);
+        level.count_time(3.);
+        level.intro_end = None;
+        level.count_time(10.5);
+        level.paused = true;
+        level.count_time(5.);
+        level.paused = false;
+        level.exit_intent = true;
+        level.count_time(5.);
+        level.exit_intent = false;
+        level.count_time(10.);
+        let results = level.results();
+        assert_eq!((results.time_used, results.time_left), (20, 39));
+
+        level.count_time(100.);
+        assert_eq!(level.results().time_left, 0);
+    }
+
+    #[test]
+    fn preview_follows_the_tile_down() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["------", "-E   -", "--   -", "------"]);
+        level.player.position = (1, 1);
+        let landing = level.preview_drag(Direction::Right).unwrap();
+        assert_eq!(landing.position, Vec2::new(2. * TILE_WIDTH, 2. * TILE_HEIGHT));
+        assert!(landing.matches.is_empty());
+        assert!(level.preview_drag(Direction::Left).is_none());
+    }
+
+    #[test]
+    fn preview_shows_the_match() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["-----", "-G G-", "-----"]);
+        level.player.position = (1, 1);
+        let landing = level.preview_drag(Direction::Right).unwrap();
+        assert_eq!(landing.position, Vec2::new(2. * TILE_WIDTH, TILE_HEIGHT));
+        assert_eq!(landing.matches.len(), 2);
+    }
+
+    #[test]
+    fn preview_is_kept_until_the_cursor_moves() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["------", "-E  E-", "------"]);
+        level.player.position = (1, 1);
+        level.update_preview();
+        assert_eq!(level.preview.as_ref().unwrap().landings.len(), 1);
+        level.preview.as_mut().unwrap().landings.clear();
+        level.update_preview();
+        assert!(level.preview.as_ref().unwrap().landings.is_empty());
+
+        level.player.position = (4, 1);
+        level.update_preview();
+        let landings = &level.preview.as_ref().unwrap().landings;
+        assert_eq!(landings[0].position, Vec2::new(3. * TILE_WIDTH, TILE_HEIGHT));
+    }
+
+    #[test]
+    fn connected_tiles_clear_as_one_match() {
+        let assets = Assets::empty();
+        let mut level = board(&assets, &["------", "-  G -", "-GGG -", "------"]);
+        assert_eq!(level.update_tiles(), [sound::Sounds::Match]);
+        assert_eq!(level.map.iter().filter(|t| t.fade_step > 0).count(), 4);
+        assert_eq!(level.clears, 4);
+    }
+
+    #[test]
+    fn falling_tiles_do_not_match_on_the_way_down() {
+        let assets = Assets::empty();
+        let rows = ["-----", "- G -", "-G  -", "--  -", "-   -", "-----"];
+        let mut level = board(&assets, &rows);
+        for _ in 0..100 {
+            assert!(!level.update_tiles().contains(&sound::Sounds::Match));
+        }
+        let landed = level
+            .map
+            .iter()
+            .find(|t| t.c == 'G' && t.position.x > TILE_WIDTH)
+            .unwrap();
+        assert_eq!(landed.position, Vec2::new(2. * TILE_WIDTH, 4. * TILE_HEIGHT));
+    }
+
+    #[test]
+    fn fast_tiles_speed_up_and_land_on_the_grid() {
+        let assets = Assets::empty();
+        let rows = ["---", "-G-", "- -", "- -", "- -", "- -", "---"];
+        let mut level = board(&assets, &rows);
+        level.physics = Physics::preset(GameSpeed::Fast);
+        let index = level.map.iter().position(|t| t.c == 'G').unwrap();
+        let mut speeds = vec![];
+        while !level.is_stable() {
+            level.update_tiles();
+            speeds.push(level.map[index].velocity.y);
+        }
+        assert_eq!(speeds[..3], [0.25, 0.5, 0.75]);
+        assert!(speeds.contains(&4.));
+        assert_eq!(level.map[index].position, Vec2::new(TILE_WIDTH, 5. * TILE_HEIGHT));
+    }
+
+    #[test]
+    fn elevators_carry_the_whole_stack() {
+        let assets = Assets::empty();
+        let rows = ["-----", "-   -", "-   -", "- E -", "- G -", "- | -", "-   -", "-----"];
+        let mut level = board(&assets, &rows);
+        let position = |level: &PlayingState, c: char| {
+            level.map.iter().find(|t| t.c == c).unwrap().position.y
+        };
+        let mut highest = f32::MAX;
+        let mut bounces = 0;
+        for _ in 0..100 {
+            bounces += level
+                .update_tiles()
+                .iter()
+                .filter(|&&snd| snd == sound::Sounds::Bounce)
+                .count();
+            let (e, g) = (position(&level, 'E'), position(&level, 'G'));
+            let elevator = position(&level, '|');
+            assert_eq!(e + TILE_HEIGHT, g);
+            assert_eq!(g + TILE_HEIGHT, elevator);
+            highest = highest.min(e);
+        }
+        // pushed up until the top tile hits the wall, then back down to the floor
+        assert_eq!(highest, TILE_HEIGHT);
+        assert!(position(&level, 'E') > TILE_HEIGHT);
+        assert_eq!(bounces, 2);
+    }
+
+    #[test]
+    fn tiles_get_off_an_elevator_once_it_parks() {
+        let assets = Assets::empty();
+        let rows = ["-----", "-   -", "- G -", "-G- -", "-|  -", "-----"];
+        let mut level = board(&assets, &rows);
+        let elevator = level.map.iter_mut().find(|t| t.c == '|').unwrap();
+        elevator.looping = false;
+        elevator.path.as_mut().unwrap().range = Some(TILE_HEIGHT);
+        for _ in 0..200 {
+            level.update_tiles();
+        }
+        // the raised tile matches, which it can't while riding
+        assert!(level.map.iter().all(|t| t.c != 'G'));
+    }
+
+    #[test]
+    fn dragging_off_an_elevator_snaps_to_the_grid() {
+        let assets = Assets::empty();
+        let rows = ["-----", "-   -", "-   -", "-G  -", "-|  -", "-----"];
+        let mut level = board(&assets, &rows);
+        for _ in 0..5 {
+            level.update_tiles();
+        }
+        let index = level.map.iter().position(|t| t.c == 'G').unwrap();
+        assert!(level.map[index].riding);
+        level.map[index].dragging_direction = Some(Direction::Right);
+        level.update_tiles();
+        let tile = &level.map[index];
+        assert_eq!(tile.position, Vec2::new(2. * TILE_WIDTH, 3. * TILE_HEIGHT));
+        assert_eq!(level.player.position, (2, 3));
+    }
+
+    /// The collision check as it was before the grid, scanning the whole map
+    fn linear_collision(level: &PlayingState, t1: &Tile, coordinates: &Vec2) -> Option<usize> {
+        level
+            .map
+            .iter()
+            .position(|tile| tile.id != t1.id && check_collision_perfect(tile, coordinates))
+    }
+
+    #[test]
+    fn grid_agrees_with_linear_scan() {
+        let assets = Assets::empty();
+        for n in 1..161 {
+            let mut level = start_level(&assets, n);
+            for _ in 0..60 {
+                for tile in level.map.iter() {
+                    let positions = [
+                        tile.position,
+                        tile.position + tile.velocity,
+                        tile.position + Vec2::new(0., SPEED),
+                    ];
+                    for position in positions.iter() {
+                        assert_eq!(
+                            level.check_collision(tile, &level.map, position),
+                            linear_collision(&level, tile, position),
+                            "level {}, tile {:?}",
+                            n,
+                            tile
+                        );
+                    }
+                }
+                level.update_tiles();
+            }
+        }
+    }
+
+    /// `matching_neighbours` as it was before the grid, scanning the whole map
+    fn linear_matches(level: &PlayingState, tile: &Tile) -> Option<TileChange> {
+        if !tile.kind.matchable || !level.is_resting(tile) {
+            return None;
+        }
+        for t in level.map.iter() {
+            if tile.id != t.id
+                && tile.c == t.c
+                && level.is_resting(t)
+                && (((tile.position.x - t.position.x).abs() == TILE_WIDTH)
+                    && ((tile.position.y - t.position.y).abs() == 0.0)
+                    || ((tile.position.y - t.position.y).abs() == TILE_WIDTH)
+                        && ((tile.position.x - t.position.x).abs() == 0.0))
+            {
+                return Some(TileChange::FadeOut);
+            }
+        }
+        None
+    }
+
+    /// Time taken to run `query` for every tile of the map, `rounds` times
+    fn time_queries<F: FnMut(&Tile) -> bool>(
+        level: &PlayingState,
+        rounds: usize,
+        mut query: F,
+    ) -> (f64, usize) {
+        let start = Instant::now();
+        let mut hits = 0;
+        for _ in 0..rounds {
+            for tile in level.map.iter() {
+                hits += query(tile) as usize;
+            }
+        }
+        (start.elapsed().as_secs_f64() * 1000., hits)
+    }
+
+    /// cargo test --release bench_grid -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_grid() {
+        let assets = Assets::empty();
+        let mut largest: Vec<(usize, usize)> = (1..161)
+            .map(|n| (levels::load_level(Path::new("levels"), n).unwrap().tiles.len(), n))
+            .collect();
+        largest.sort_unstable();
+
+        let rounds = 5000;
+        for &(tiles, n) in largest.iter().rev().take(5) {
+            let level = start_level(&assets, n);
+            let below = |tile: &Tile| tile.position + Vec2::new(0., SPEED);
+
+            let (linear_collisions, expected) = time_queries(&level, rounds, |tile| {
+                linear_collision(&level, tile, &below(tile)).is_some()
+            });
+            let (grid_collisions, hits) = time_queries(&level, rounds, |tile| {
+                level.check_collision(tile, &level.map, &below(tile)).is_some()
+            });
+            assert_eq!(hits, expected);
+
+            let (linear_matches, expected) = time_queries(&level, rounds, |tile| {
+                linear_matches(&level, tile).is_some()
+            });
+            let (grid_matches, hits) = time_queries(&level, rounds, |tile| {
+                !level.matching_neighbours(tile).is_empty()
+            });
+            assert_eq!(hits, expected);
+
+            let mut level = start_level(&assets, n);
+            let start = Instant::now();
+            for _ in 0..rounds {
+                level.update_tiles();
+            }
+            let frames = rounds as f64 / start.elapsed().as_secs_f64();
+
+            println!(
+                "level {:3} ({:3} tiles): collisions {:.1}ms -> {:.1}ms ({:.1}x), \
+                 matches {:.1}ms -> {:.1}ms ({:.1}x), {:.0} frames/s",
+                n,
+                tiles,
+                linear_collisions,
+                grid_collisions,
+                linear_collisions / grid_collisions,
+                linear_matches,
+                grid_matches,
+                linear_matches / grid_matches,
+                frames
+            );
+        }
+    }
 }
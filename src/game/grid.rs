@@ -0,0 +1,74 @@
+use macroquad::prelude::Vec2;
+
+use super::{
+    playing_state::{TILE_HEIGHT, TILE_WIDTH},
+    tile::Tile,
+};
+
+/// Occupancy grid of the board, each cell holds the indices (into the map)
+/// of every tile overlapping it. A moving tile overlaps up to four cells.
+#[derive(Clone)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Grid {
+            width,
+            height,
+            cells: vec![vec![]; width * height],
+        }
+    }
+
+    /// Re-indexes every tile of the map
+    pub fn rebuild(&mut self, map: &[Tile]) {
+        for cell in self.cells.iter_mut() {
+            cell.clear();
+        }
+        for (index, tile) in map.iter().enumerate() {
+            let (x0, y0, x1, y1) = self.cell_range(&tile.position);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    self.cells[y * self.width + x].push(index);
+                }
+            }
+        }
+    }
+
+    /// Indices of the tiles overlapping cell (x, y)
+    pub fn at(&self, x: usize, y: usize) -> &[usize] {
+        if x >= self.width || y >= self.height {
+            return &[];
+        }
+        &self.cells[y * self.width + x]
+    }
+
+    /// Calls `f` with the index of every tile that may overlap a tile placed
+    /// at `position`, possibly more than once if it overlaps several cells
+    pub fn for_each_candidate<F: FnMut(usize)>(&self, position: &Vec2, mut f: F) {
+        let (x0, y0, x1, y1) = self.cell_range(position);
+        for y in y0..=y1 {
+            for cell in &self.cells[y * self.width + x0..=y * self.width + x1] {
+                for &index in cell {
+                    f(index);
+                }
+            }
+        }
+    }
+
+    /// First and last cells covered by a tile at `position`, clamped to the grid
+    fn cell_range(&self, position: &Vec2) -> (usize, usize, usize, usize) {
+        // float to int casts saturate, so anything left of or above the grid lands on 0
+        let (x0, y0) = (
+            (position.x / TILE_WIDTH) as usize,
+            (position.y / TILE_HEIGHT) as usize,
+        );
+        let x1 = x0 + (position.x > x0 as f32 * TILE_WIDTH) as usize;
+        let y1 = y0 + (position.y > y0 as f32 * TILE_HEIGHT) as usize;
+        let (max_x, max_y) = (self.width.saturating_sub(1), self.height.saturating_sub(1));
+        (x0.min(max_x), y0.min(max_y), x1.min(max_x), y1.min(max_y))
+    }
+}
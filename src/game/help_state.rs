@@ -1,5 +1,81 @@
+use async_trait::async_trait;
+use macroquad::prelude::*;
+
+use super::{
+    input::{key_name, Action, KeyBindings},
+    sound::Sounds,
+    states::{GameContext, Playable, Transition},
+};
+
+/// How a level is played
+const RULES: [&str; 4] = [
+    "BLOCKS OF A KIND THAT TOUCH VANISH.",
+    "GRAB A BLOCK TO DRAG IT SIDEWAYS,",
+    "IT FALLS IF NOTHING HOLDS IT UP.",
+    "CLEAR EVERY BLOCK TO WIN THE LEVEL.",
+];
+
+/// Shows the rules and the keys they're played with
 pub struct HelpState {
+    font: Font,
+    keys: KeyBindings,
+}
 
+impl HelpState {
+    pub fn new(ctx: &GameContext) -> Self {
+        HelpState {
+            font: ctx.assets.font,
+            keys: ctx.config.keys,
+        }
+    }
 
+    fn text_params(&self, color: Color) -> TextParams {
+        let (fs, fc, fa) = camera_font_scale(6.);
+        TextParams {
+            font: self.font,
+            font_size: fs,
+            font_scale: fc,
+            font_scale_aspect: fa,
+            color,
+        }
+    }
+
+    fn draw(&self) {
+        clear_background(BLACK);
+        draw_text_ex("INSTRUCTIONS", 120., 20., self.text_params(GREEN));
+        for (n, line) in RULES.iter().enumerate() {
+            let y = 40. + n as f32 * 10.;
+            draw_text_ex(line, 60., y, self.text_params(WHITE));
+        }
+
+        let mut keys: Vec<(String, &str)> = Action::ALL
+            .iter()
+            .map(|&action| (key_name(self.keys.key(action)), action.name()))
+            .collect();
+        keys.push((key_name(KeyCode::M), "MUTE"));
+        keys.push((key_name(KeyCode::Escape), "LEAVE THE LEVEL"));
+        for (n, (key, action)) in keys.iter().enumerate() {
+            let y = 90. + n as f32 * 10.;
+            draw_text_ex(&key.to_uppercase(), 60., y, self.text_params(LIGHTGRAY));
+            draw_text_ex(action, 160., y, self.text_params(WHITE));
+        }
+        draw_text_ex("PRESS ENTER", 127., 190., self.text_params(GREEN));
+    }
 }
 
+#[async_trait]
+impl Playable for HelpState {
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition {
+        loop {
+            ctx.update();
+            set_camera(&Camera2D::from_display_rect(Rect::new(0., 0., 320., 200.)));
+            self.draw();
+
+            if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::Escape) {
+                ctx.mixer.play_sound(Sounds::MenuSelect).await;
+                return Transition::Pop;
+            }
+            ctx.next_frame().await;
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use macroquad::input::KeyCode;
+
+use super::playing_state::Direction;
+
+/// Most moves kept waiting at once, older ones are dropped
+const CAPACITY: usize = 4;
+
+/// A drag the player asked for while the board was still moving
+struct BufferedMove {
+    direction: Direction,
+    issued: f64,
+}
+
+/// Queue of drags waiting for the board to settle down, each one is
+/// forgotten if it can't be carried out within `window` seconds
+pub struct InputBuffer {
+    moves: VecDeque<BufferedMove>,
+    window: f64,
+}
+
+impl InputBuffer {
+    pub fn new(window: f64) -> Self {
+        InputBuffer {
+            moves: VecDeque::with_capacity(CAPACITY),
+            window,
+        }
+    }
+
+    pub fn set_window(&mut self, window: f64) {
+        self.window = window;
+    }
+
+    /// A zero window turns buffering off, moves are then carried out right away
+    pub fn is_enabled(&self) -> bool {
+        self.window > 0.
+    }
+
+    pub fn push(&mut self, direction: Direction, now: f64) {
+        if self.moves.len() == CAPACITY {
+            self.moves.pop_front();
+        }
+        self.moves.push_back(BufferedMove {
+            direction,
+            issued: now,
+        });
+    }
+
+    /// Oldest move that is still within the window
+    pub fn pop(&mut self, now: f64) -> Option<Direction> {
+        while let Some(m) = self.moves.pop_front() {
+            if now - m.issued <= self.window {
+                return Some(m.direction);
+            }
+        }
+        None
+    }
+
+    pub fn clear(&mut self) {
+        self.moves.clear();
+    }
+}
+
+/// DAS style auto-repeat for a held key: it fires when pressed, again
+/// after `delay` seconds and then every `interval` seconds until released
+pub struct KeyRepeat {
+    pub delay: f64,
+    pub interval: f64,
+    next: Option<f64>, // when it fires again, if held
+}
+
+impl KeyRepeat {
+    pub fn new(delay: f64, interval: f64) -> Self {
+        KeyRepeat {
+            delay,
+            interval,
+            next: None,
+        }
+    }
+
+    /// Whether the key fires this frame
+    pub fn update(&mut self, pressed: bool, down: bool, now: f64) -> bool {
+        if pressed {
+            self.next = Some(now + self.delay);
+            return true;
+        }
+        match self.next {
+            Some(next) if down && now >= next => {
+                self.next = Some(now + self.interval);
+                true
+            }
+            _ if !down => {
+                self.next = None;
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Something the player does with a key of their own choosing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    Grab,
+    Pause,
+    Restart,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Left,
+        Action::Right,
+        Action::Up,
+        Action::Down,
+        Action::Grab,
+        Action::Pause,
+        Action::Restart,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Left => "LEFT",
+            Action::Right => "RIGHT",
+            Action::Up => "UP",
+            Action::Down => "DOWN",
+            Action::Grab => "GRAB",
+            Action::Pause => "PAUSE",
+            Action::Restart => "RESTART",
+        }
+    }
+}
+
+/// Keys that can be bound to an action. Escape, Enter and M are kept for
+/// leaving, menus and muting.
+#[rustfmt::skip]
+pub const BINDABLE_KEYS: [KeyCode; 56] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::N, KeyCode::O,
+    KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V,
+    KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z, KeyCode::Key0, KeyCode::Key1,
+    KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6, KeyCode::Key7,
+    KeyCode::Key8, KeyCode::Key9, KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down,
+    KeyCode::Space, KeyCode::Tab, KeyCode::Backspace, KeyCode::LeftShift, KeyCode::RightShift,
+    KeyCode::LeftControl, KeyCode::RightControl, KeyCode::LeftAlt, KeyCode::RightAlt,
+    KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon, KeyCode::Kp0,
+    KeyCode::Kp2, KeyCode::Kp4, KeyCode::Kp6,
+];
+
+/// Name of a key, as written in the settings
+pub fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+/// Looks up one of the `BINDABLE_KEYS` by its name, in any case
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|&k| key_name(k).eq_ignore_ascii_case(name))
+}
+
+/// The key bound to each `Action`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyBindings {
+    keys: [KeyCode; 7], // in the order of `Action::ALL`
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            keys: [
+                KeyCode::Left,
+                KeyCode::Right,
+                KeyCode::Up,
+                KeyCode::Down,
+                KeyCode::Space,
+                KeyCode::P,
+                KeyCode::R,
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys[action as usize]
+    }
+
+    /// Binds a key to an action. An action the key was bound to already gets
+    /// the action's old key, so that every action keeps a key of its own.
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        let old = self.keys[action as usize];
+        for k in self.keys.iter_mut().filter(|k| **k == key) {
+            *k = old;
+        }
+        self.keys[action as usize] = key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_expire_after_the_window() {
+        let mut buffer = InputBuffer::new(0.5);
+        buffer.push(Direction::Left, 1.0);
+        buffer.push(Direction::Right, 1.4);
+        assert_eq!(buffer.pop(1.6), Some(Direction::Right));
+        assert_eq!(buffer.pop(1.6), None);
+    }
+
+    #[test]
+    fn oldest_moves_are_dropped_when_full() {
+        let mut buffer = InputBuffer::new(1.0);
+        for _ in 0..CAPACITY {
+            buffer.push(Direction::Left, 0.);
+        }
+        buffer.push(Direction::Right, 0.);
+        for _ in 1..CAPACITY {
+            assert_eq!(buffer.pop(0.), Some(Direction::Left));
+        }
+        assert_eq!(buffer.pop(0.), Some(Direction::Right));
+    }
+
+    #[test]
+    fn rebinding_a_used_key_swaps_them() {
+        let mut keys = KeyBindings::default();
+        keys.bind(Action::Grab, KeyCode::P);
+        assert_eq!(keys.key(Action::Grab), KeyCode::P);
+        assert_eq!(keys.key(Action::Pause), KeyCode::Space);
+        assert_eq!(key_from_name("space"), Some(KeyCode::Space));
+        assert_eq!(key_from_name("Escape"), None);
+    }
+
+    #[test]
+    fn held_keys_repeat_after_the_delay() {
+        let mut key = KeyRepeat::new(0.25, 0.125);
+        assert!(key.update(true, true, 0.));
+        assert!(!key.update(false, true, 0.125));
+        assert!(key.update(false, true, 0.25));
+        assert!(!key.update(false, true, 0.3));
+        assert!(key.update(false, true, 0.375));
+        assert!(!key.update(false, false, 0.5));
+        assert!(!key.update(false, true, 1.));
+    }
+}
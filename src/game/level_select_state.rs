@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use macroquad::prelude::*;
+
+use super::{
+    game_logic::LevelInfo,
+    levels,
+    menu::{Menu, MenuEvent, MenuItem},
+    playing_state::{TILE_HEIGHT, TILE_WIDTH},
+    records::{clock, Records},
+    states::{GameContext, Playable, StateType, Transition},
+};
+
+/// Levels shown at once, a world's worth
+const COLUMNS: usize = 5;
+const ROWS: usize = 2;
+const CELL_WIDTH: f32 = 64.;
+const CELL_HEIGHT: f32 = 88.;
+const GRID_TOP: f32 = 22.;
+/// Largest board thumbnail that fits a cell
+const THUMBNAIL: (f32, f32) = (56., 40.);
+
+/// A page of levels to pick from, one world at a time
+pub struct LevelSelectState {
+    font: Font,
+    tiles: Texture2D,
+    levels: Vec<LevelInfo>,
+    menu: Menu<usize>,
+}
+
+impl LevelSelectState {
+    pub fn new(ctx: &GameContext) -> Self {
+        let pack = &ctx.session.pack;
+        let levels: Vec<LevelInfo> = (1..=levels::count_levels(pack))
+            .filter_map(|n| match levels::load_level(pack, n) {
+                Ok(level) => Some(level),
+                Err(e) => {
+                    println!("Leaving out {}", e);
+                    None
+                }
+            })
+            .collect();
+        let items = levels
+            .iter()
+            .map(|level| MenuItem::button(level.level, &level.name))
+            .collect();
+        let mut menu = Menu::new("", items);
+        menu.columns = COLUMNS;
+        LevelSelectState {
+            font: ctx.assets.font,
+            tiles: ctx.assets.tiles,
+            levels,
+            menu,
+        }
+    }
+
+    fn text_params(&self, color: Color) -> TextParams {
+        let (fs, fc, fa) = camera_font_scale(6.);
+        TextParams {
+            font: self.font,
+            font_size: fs,
+            font_scale: fc,
+            font_scale_aspect: fa,
+            color,
+        }
+    }
+
+    /// The board shrunk to fit `THUMBNAIL`, centred on `(x, y)`
+    fn draw_thumbnail(&self, level: &LevelInfo, x: f32, y: f32, tint: Color) {
+        let scale = (THUMBNAIL.0 / (level.width as f32 * TILE_WIDTH))
+            .min(THUMBNAIL.1 / (level.height as f32 * TILE_HEIGHT));
+        let (w, h) = (TILE_WIDTH * scale, TILE_HEIGHT * scale);
+        let left = x - level.width as f32 * w / 2.;
+        let top = y - level.height as f32 * h / 2.;
+        for tile in &level.blanks {
+            let (tx, ty) = (tile.position.x * scale, tile.position.y * scale);
+            draw_rectangle(left + tx, top + ty, w, h, BLACK);
+        }
+        for tile in &level.tiles {
+            let params = DrawTextureParams {
+                dest_size: Some(Vec2::new(w, h)),
+                source: Some(Rect::new(
+                    tile.kind.sprite as f32,
+                    0.,
+                    TILE_WIDTH,
+                    TILE_HEIGHT,
+                )),
+                ..Default::default()
+            };
+            let color = Color::new(
+                tile.kind.tint.r * tint.r,
+                tile.kind.tint.g * tint.g,
+                tile.kind.tint.b * tint.b,
+                tile.kind.tint.a,
+            );
+            let (tx, ty) = (tile.position.x * scale, tile.position.y * scale);
+            draw_texture_ex(self.tiles, left + tx, top + ty, color, params);
+        }
+    }
+
+    fn draw(&self, records: &Records) {
+        clear_background(DARKGRAY);
+        let selection = self.menu.selection();
+        let page = selection / (COLUMNS * ROWS);
+        let first = page * COLUMNS * ROWS;
+        let world = match self.levels.get(first) {
+            Some(level) => level.world,
+            None => return,
+        };
+        draw_text_ex(
+            &format!("WORLD {}", world),
+            135.,
+            14.,
+            self.text_params(GREEN),
+        );
+
+        for (n, level) in self
+            .levels
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(COLUMNS * ROWS)
+        {
+            let x = ((n - first) % COLUMNS) as f32 * CELL_WIDTH;
+            let y = GRID_TOP + ((n - first) / COLUMNS) as f32 * CELL_HEIGHT;
+            let unlocked = records.unlocked(level.level);
+            let color = if n == selection { WHITE } else { LIGHTGRAY };
+            if n == selection {
+                draw_rectangle_lines(x + 1., y, CELL_WIDTH - 2., CELL_HEIGHT - 2., 1., WHITE);
+            }
+
+            draw_text_ex(&level.name, x + 4., y + 9., self.text_params(color));
+            let tint = if unlocked { WHITE } else { GRAY };
+            self.draw_thumbnail(level, x + CELL_WIDTH / 2., y + 34., tint);
+
+            let tp = self.text_params(color);
+            match records.get(level.level) {
+                _ if !unlocked => draw_text_ex("LOCKED", x + 4., y + 66., tp),
+                Some(best) => {
+                    draw_text_ex(&format!("{}", best.score), x + 4., y + 66., tp);
+                    draw_text_ex(&clock(best.time), x + 4., y + 76., tp);
+                }
+                None => draw_text_ex("NOT CLEARED", x + 4., y + 66., tp),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Playable for LevelSelectState {
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition {
+        loop {
+            ctx.update();
+            set_camera(&Camera2D::from_display_rect(Rect::new(0., 0., 320., 200.)));
+            self.draw(&ctx.records);
+
+            match self.menu.update(&mut ctx.mixer).await {
+                Some(MenuEvent::Selected(level)) if ctx.records.unlocked(level) => {
+                    ctx.session.score = 0;
+                    return Transition::Replace(StateType::Playing(level));
+                }
+                Some(MenuEvent::Back) => return Transition::Pop,
+                _ => {}
+            }
+            ctx.next_frame().await;
+        }
+    }
+}
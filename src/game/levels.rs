@@ -1,46 +1,90 @@
-use super::tile::*;
+use super::{sound::Sounds, tile::*, tile_kind::TileKind};
 use crate::game::{
     game_logic::LevelInfo,
     playing_state::{SPEED, TILE_HEIGHT, TILE_WIDTH},
 };
 use macroquad::prelude::*;
 use regex::Regex;
+use std::path::Path;
 
-pub fn load_level(n: usize) -> LevelInfo {
-    let s = std::fs::read_to_string(format!("levels/{}.txt", n)).unwrap();
-    let tokens: Vec<&str> = s.split("\n").collect();
+/// Reads a level, see `validate_level` for what makes one broken
+pub fn load_level(pack: &Path, n: usize) -> Result<LevelInfo, String> {
+    read_level(pack, n).map_err(|e| format!("level {}: {}", n, e))
+}
+
+/// Complains about anything that would keep a level from being played, such
+/// as elevator lines that can't be read or nowhere to put the cursor
+pub fn validate_level(pack: &Path, n: usize) -> Result<(), String> {
+    read_level(pack, n).map(|_| ())
+}
+
+/// How many levels there are in a row from level 1
+pub fn count_levels(pack: &Path) -> usize {
+    (1..).take_while(|n| pack.join(format!("{}.txt", n)).is_file()).count()
+}
+
+/// Number of the level called `name`, such as 5-3, or numbered `name`
+pub fn find_level(pack: &Path, name: &str) -> Option<usize> {
+    let count = count_levels(pack);
+    if let Ok(n) = name.parse() {
+        return Some(n).filter(|n| (1..=count).contains(n));
+    }
+    (1..=count).find(|n| {
+        let text = std::fs::read_to_string(pack.join(format!("{}.txt", n))).unwrap_or_default();
+        let first = text.lines().next().unwrap_or_default();
+        extract_name(first).is_ok_and(|(_, found)| found == name)
+    })
+}
 
-    let time = extract_seconds(&tokens[2]);
-    let tokens = &tokens[4..];
+fn read_level(pack: &Path, n: usize) -> Result<LevelInfo, String> {
+    let path = pack.join(format!("{}.txt", n));
+    let s = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_level(&s, n)
+}
+
+/// Builds a level out of the text of its file. The map may mark the cell
+/// the cursor starts on with `@`, otherwise it starts on the empty cell
+/// inside the walls nearest the middle of the map.
+fn parse_level(s: &str, n: usize) -> Result<LevelInfo, String> {
+    let file = LevelFile::parse(s)?;
+    let (world, name) = extract_name(&file.header[0])?;
+    let password = extract_password(&file.header[1])?;
+    let time = extract_seconds(&file.header[2])?;
+    let music = match file.music {
+        Some(n) => Sounds::PLAYLIST[n - 1],
+        None => Sounds::world_music(world),
+    };
     let mut map = vec![];
     let mut blanks = vec![]; // a vec of blank tiles, to draw the background
 
-    let mut rows: Vec<String> = tokens
-        .iter()
-        .map(|s| s.chars().step_by(2).collect::<String>())
-        .filter(|r| !r.trim().is_empty())
-        .collect();
-
-//    println!("{:?}", rows);
+    let mut rows: Vec<Vec<char>> = file.rows.iter().map(|r| r.chars().collect()).collect();
 
     let map_height = rows.len();
-    let map_width = &rows.iter().map(|c| c.len()).max().unwrap();
-    let mut tile_index = 1;
+    let map_width = rows.iter().map(|r| r.len()).max().ok_or("the map is empty")?;
+    let mut marked = None;
     for (y, line) in rows.iter_mut().enumerate() {
-        for _ in 0..map_width - line.len() {
-            line.push(' ');
+        line.resize(map_width, ' ');
+        for (x, c) in line.iter_mut().enumerate() {
+            if *c == '@' {
+                if marked.is_some() {
+                    return Err(format!("a second start @ at {},{}", x, y));
+                }
+                marked = Some((x, y));
+                *c = ' ';
+            }
+            if TileKind::of(*c).is_none() {
+                return Err(format!("unknown tile {:?} at {},{}", c, x, y));
+            }
         }
+    }
 
-        // get a vec of tuples, where tuple.0 is the index
-
-        let v: Vec<_> = line.match_indices('-').collect();
-        debug_assert!(!v.is_empty());
-        let first_brick_idx = v[0].0;
-        let last_brick_idx = v.last().unwrap().0;
-
-//        println!("First: {}, Last: {}", first_brick_idx, last_brick_idx);
-        for (x, c) in line.chars().enumerate() {
-            if c != '-' && x > first_brick_idx && x < last_brick_idx {
+    let middle = ((map_width / 2).saturating_sub(1), map_height / 2);
+    let inside = interior(&rows, marked.unwrap_or(middle));
+    let start = find_start(&rows, &inside, marked, middle)?;
+    let mut tile_index = 1;
+    for (y, line) in rows.iter().enumerate() {
+        for (x, &c) in line.iter().enumerate() {
+            if inside[y][x] {
                 blanks.push(Tile::new(
                     tile_index,
                     c,
@@ -52,52 +96,495 @@ pub fn load_level(n: usize) -> LevelInfo {
                 continue;
             }
 
-            let mut t = Tile::new(
+            map.push(Tile::new(
                 tile_index,
                 c,
                 x as f32 * TILE_HEIGHT,
                 y as f32 * TILE_HEIGHT,
-            );
-
-            if c == '|' {
-                t.velocity = Vec2::new(0., -SPEED);
-                t.looping = true;
-                t.riding = true;
-            }
-            if c == '~' {
-                t.velocity = Vec2::new(SPEED, 0.);
-                t.looping = true;
-                t.riding = true;
-            }
-            if c != ' ' {
-                map.push(t);
-                tile_index += 1;
-            }
+            ));
+            tile_index += 1;
         }
     }
 
+    for line in file.elevators.iter() {
+        let (x, y, elevator) = parse_elevator(line)?;
+        let position = Vec2::new(x as f32 * TILE_WIDTH, y as f32 * TILE_HEIGHT);
+        let tile = map
+            .iter_mut()
+            .find(|t: &&mut Tile| t.position == position && t.is_elevator())
+            .ok_or_else(|| format!("no elevator at {},{}", x, y))?;
+        elevator.apply(tile);
+    }
+
     let offset_y = (200. - map_height as f32 * TILE_HEIGHT) / 2.;
-    let offset_x = (320. + 100. - *map_width as f32 * TILE_WIDTH) / 2.;
-    let info = LevelInfo {
+    let offset_x = (320. + 100. - map_width as f32 * TILE_WIDTH) / 2.;
+    Ok(LevelInfo {
         tiles: map,
         blanks,
-        width: *map_width,
+        start,
+        width: map_width,
         height: map_height,
         offset_x,
         offset_y,
         level: n,
-        time: time,
+        world,
+        name,
+        password,
+        music,
+        time,
+    })
+}
+
+/// How the cells of a map are written down in a level file
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    /// Every cell followed by a space, as in the original levels
+    Spaced,
+    /// One character per cell
+    Compact,
+}
+
+impl Encoding {
+    /// Guesses the encoding of map rows: spaced maps leave every other
+    /// column blank, which compact ones never do as walls go all around
+    fn detect(lines: &[&str]) -> Encoding {
+        let spaced = lines
+            .iter()
+            .all(|l| l.chars().skip(1).step_by(2).all(|c| c == ' '));
+        if spaced {
+            Encoding::Spaced
+        } else {
+            Encoding::Compact
+        }
+    }
+}
+
+/// A level file split up into its parts.
+///
+/// ```text
+/// Level 1-1
+/// Password: AAAA
+/// Time: 1'00"
+/// Grid: compact
+/// ------
+/// -G  G-
+/// ------
+///
+/// Music: 2
+/// Elevator 2,1: up
+/// ```
+///
+/// The line after the time may declare the map's encoding with
+/// `Grid: compact` or `Grid: spaced`, else it is left blank and the
+/// encoding is told from the rows themselves. `Music` picks a track of the
+/// playlist other than the world's own.
+pub struct LevelFile {
+    pub header: Vec<String>, // title, password and time
+    pub encoding: Encoding,
+    pub rows: Vec<String>,      // one character per cell
+    pub elevators: Vec<String>, // see `parse_elevator`
+    pub music: Option<usize>,   // track of `Sounds::PLAYLIST` played, from 1
+}
+
+impl LevelFile {
+    pub fn parse(text: &str) -> Result<LevelFile, String> {
+        let lines: Vec<&str> = text.lines().map(|l| l.trim_end_matches('\r')).collect();
+        if lines.len() < 4 {
+            return Err("the level file is too short".to_string());
+        }
+        let header = lines[..3].iter().map(|l| l.trim_end().to_string()).collect();
+        let (directives, grid): (Vec<&str>, Vec<&str>) = lines[4..]
+            .iter()
+            .filter(|l| !l.trim().is_empty())
+            .partition(|l| l.starts_with("Elevator") || l.starts_with("Music:"));
+        let (music, elevators): (Vec<&str>, Vec<&str>) =
+            directives.iter().partition(|l| l.starts_with("Music:"));
+        let music = match music.last() {
+            Some(line) => {
+                let n = line["Music:".len()..].trim();
+                match n.parse::<usize>() {
+                    Ok(n) if (1..=Sounds::PLAYLIST.len()).contains(&n) => Some(n),
+                    _ => return Err(format!("no track {:?} in the playlist", n)),
+                }
+            }
+            None => None,
+        };
+        let encoding = match lines[3].trim() {
+            "" => Encoding::detect(&grid),
+            "Grid: spaced" => Encoding::Spaced,
+            "Grid: compact" => Encoding::Compact,
+            other => return Err(format!("unknown grid encoding {:?}", other)),
+        };
+        let rows = grid
+            .iter()
+            .map(|l| match encoding {
+                Encoding::Spaced => l.chars().step_by(2).collect::<String>(),
+                Encoding::Compact => l.to_string(),
+            })
+            .map(|r| r.trim_end().to_string())
+            .collect();
+        Ok(LevelFile {
+            header,
+            encoding,
+            rows,
+            elevators: elevators.iter().map(|l| l.trim_end().to_string()).collect(),
+            music,
+        })
+    }
+
+    /// Writes the level back in the canonical layout. Spaced maps are left
+    /// undeclared as in the original levels, compact ones are declared.
+    pub fn to_text(&self) -> String {
+        let mut lines = self.header.clone();
+        lines.push(match self.encoding {
+            Encoding::Spaced => String::new(),
+            Encoding::Compact => "Grid: compact".to_string(),
+        });
+        for row in self.rows.iter() {
+            lines.push(match self.encoding {
+                Encoding::Spaced => {
+                    let cells: Vec<String> = row.chars().map(String::from).collect();
+                    cells.join(" ")
+                }
+                Encoding::Compact => row.clone(),
+            });
+        }
+        if !self.elevators.is_empty() || self.music.is_some() {
+            lines.push(String::new());
+            lines.extend(self.music.map(|n| format!("Music: {}", n)));
+            lines.extend(self.elevators.iter().cloned());
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// Rewrites a level file in the canonical layout, in the given encoding or
+/// else the one it already uses
+pub fn format_level(text: &str, encoding: Option<Encoding>) -> Result<String, String> {
+    let mut file = LevelFile::parse(text)?;
+    if let Some(encoding) = encoding {
+        file.encoding = encoding;
+    }
+    Ok(file.to_text())
+}
+
+/// Cells inside the walls, found by flood filling from the cursor start.
+/// Every tile is filled from as well, so rooms the cursor can't reach from
+/// its start still get their background. Areas reaching the edge of the map
+/// aren't enclosed, so they are left out, as is a start stuck in a wall.
+fn interior(rows: &[Vec<char>], start: (usize, usize)) -> Vec<Vec<bool>> {
+    let (width, height) = (rows[0].len(), rows.len());
+    let mut inside = vec![vec![false; width]; height];
+    let mut seen = vec![vec![false; width]; height];
+    let mut seeds = vec![start];
+    for (y, row) in rows.iter().enumerate() {
+        for (x, &c) in row.iter().enumerate() {
+            if c != ' ' {
+                seeds.push((x, y));
+            }
+        }
+    }
+
+    for seed in seeds {
+        let mut area = vec![];
+        let mut enclosed = true;
+        let mut pending = vec![seed];
+        while let Some((x, y)) = pending.pop() {
+            if x >= width || y >= height || seen[y][x] || rows[y][x] == '-' {
+                continue;
+            }
+            seen[y][x] = true;
+            area.push((x, y));
+            if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                enclosed = false;
+                continue;
+            }
+            pending.extend([(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]);
+        }
+        if enclosed {
+            for (x, y) in area {
+                inside[y][x] = true;
+            }
+        }
+    }
+    inside
+}
+
+/// Cell the cursor starts on, the one marked with `@` if it's inside the
+/// walls, else the empty interior cell nearest `middle`
+fn find_start(
+    rows: &[Vec<char>],
+    inside: &[Vec<bool>],
+    marked: Option<(usize, usize)>,
+    middle: (usize, usize),
+) -> Result<(usize, usize), String> {
+    if let Some((x, y)) = marked {
+        if !inside[y][x] {
+            return Err(format!("the start @ at {},{} is outside the walls", x, y));
+        }
+        return Ok((x, y));
+    }
+    let distance = |(x, y): (usize, usize)| {
+        let (dx, dy) = (x as isize - middle.0 as isize, y as isize - middle.1 as isize);
+        dx * dx + dy * dy
     };
+    rows.iter()
+        .enumerate()
+        .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, &c)| (x, y, c)))
+        .filter(|&(x, y, c)| c == ' ' && inside[y][x])
+        .map(|(x, y, _)| (x, y))
+        .min_by_key(|&cell| distance(cell))
+        .ok_or_else(|| "no empty cell inside the walls to start on".to_string())
+}
+
+/// World number and full name, such as `3-2`, of the `Level 3-2` line
+fn extract_name(str: &str) -> Result<(usize, String), String> {
+    let re = Regex::new(r"Level ((\d+)-\d+)").unwrap();
+    let captures = re
+        .captures(str)
+        .ok_or_else(|| format!("no level name in {:?}", str))?;
+    Ok((captures[2].parse().unwrap(), captures[1].to_string()))
+}
 
-    info
+fn extract_password(str: &str) -> Result<String, String> {
+    let re = Regex::new(r"Password: (\w+)").unwrap();
+    let captures = re
+        .captures(str)
+        .ok_or_else(|| format!("no password in {:?}", str))?;
+    Ok(captures[1].to_string())
 }
 
-fn extract_seconds(str: &str) -> u16 {
+fn extract_seconds(str: &str) -> Result<u16, String> {
     let re = Regex::new(r"Time: (\d)'(\d{2})").unwrap();
-    let captures = re.captures(str).unwrap();
+    let captures = re
+        .captures(str)
+        .ok_or_else(|| format!("no time limit in {:?}", str))?;
     let minutes: u16 = captures.get(1).unwrap().as_str().parse().unwrap();
     let seconds: u16 = captures.get(2).unwrap().as_str().parse().unwrap();
 
     println!("Found time: {},{}", minutes, seconds);
-    return minutes * 60 + seconds;
+    Ok(minutes * 60 + seconds)
+}
+
+/// How one elevator of a level travels
+#[derive(Debug, PartialEq)]
+struct ElevatorSpec {
+    direction: Vec2,
+    speed: Option<f32>,
+    range: Option<f32>, // in cells
+    pause: u32,
+    once: bool,
+}
+
+impl ElevatorSpec {
+    fn apply(&self, tile: &mut Tile) {
+        tile.velocity = self.direction * self.speed.unwrap_or(SPEED);
+        tile.looping = !self.once;
+        if let Some(path) = tile.path.as_mut() {
+            path.direction = self.direction;
+            path.speed = self.speed;
+            path.range = self.range.map(|cells| cells * TILE_WIDTH);
+            path.pause = self.pause;
+        }
+    }
+}
+
+/// Reads a line describing an elevator, which goes after the map:
+///
+/// `Elevator <column>,<row>: <up|down|left|right> [speed <pixels per frame>]
+/// [range <cells>] [pause <frames>] [once]`
+///
+/// Columns and rows count from 0 at the top left of the map. Without a `speed`
+/// it goes as fast as the game speed's elevators. `range` is how
+/// far it travels from where it starts, `pause` how many frames it waits at either
+/// end, and `once` stops it for good at the far end instead of turning back.
+fn parse_elevator(line: &str) -> Result<(usize, usize, ElevatorSpec), String> {
+    let re = Regex::new(r"^Elevator (\d+),(\d+):(.*)$").unwrap();
+    let captures = re
+        .captures(line.trim_end())
+        .ok_or_else(|| format!("cannot read {:?}", line))?;
+    let x: usize = captures[1].parse().unwrap();
+    let y: usize = captures[2].parse().unwrap();
+
+    let mut spec = ElevatorSpec {
+        direction: Vec2::ZERO,
+        speed: None,
+        range: None,
+        pause: 0,
+        once: false,
+    };
+    let mut words = captures[3].split_whitespace();
+    while let Some(word) = words.next() {
+        let invalid = || format!("{} needs a number in {:?}", word, line);
+        let mut number = || words.next().and_then(|w| w.parse::<f32>().ok());
+        match word {
+            "up" => spec.direction = Vec2::new(0., -1.),
+            "down" => spec.direction = Vec2::new(0., 1.),
+            "left" => spec.direction = Vec2::new(-1., 0.),
+            "right" => spec.direction = Vec2::new(1., 0.),
+            "speed" => spec.speed = Some(number().filter(|v| *v > 0.).ok_or_else(invalid)?),
+            "range" => spec.range = Some(number().filter(|v| *v >= 0.).ok_or_else(invalid)?),
+            // whole frames only
+            "pause" => {
+                spec.pause = words
+                    .next()
+                    .and_then(|w| w.parse().ok())
+                    .ok_or_else(invalid)?
+            }
+            "once" => spec.once = true,
+            _ => return Err(format!("unknown word {:?} in {:?}", word, line)),
+        }
+    }
+    if spec.direction == Vec2::ZERO {
+        return Err(format!("no direction in {:?}", line));
+    }
+    Ok((x, y, spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_elevator_lines() {
+        let (x, y, spec) =
+            parse_elevator("Elevator 3,5: left speed 0.5 range 4 pause 30 once").unwrap();
+        assert_eq!((x, y), (3, 5));
+        assert_eq!(
+            spec,
+            ElevatorSpec {
+                direction: Vec2::new(-1., 0.),
+                speed: Some(0.5),
+                range: Some(4.),
+                pause: 30,
+                once: true,
+            }
+        );
+        assert!(parse_elevator("Elevator 3,5: speed 2").is_err());
+        assert!(parse_elevator("Elevator 3,5: up speed").is_err());
+        assert!(parse_elevator("Elevator 3,5: up speed 0").is_err());
+        assert!(parse_elevator("Elevator 3,5: up pause 1.5").is_err());
+        assert!(parse_elevator("Elevator 3,5: up pause -2").is_err());
+        assert!(parse_elevator("Elevator 3,5: up sideways").is_err());
+    }
+
+    #[test]
+    fn the_elevator_on_3_2_rises_three_cells() {
+        let info = load_level(Path::new("levels"), 22).unwrap();
+        let elevator = info.tiles.iter().find(|t| t.is_elevator()).unwrap();
+        let path = elevator.path.as_ref().unwrap();
+        assert_eq!(path.range, Some(3. * TILE_HEIGHT));
+        assert_eq!(path.direction, Vec2::new(0., -1.));
+    }
+
+    /// A level file around the given map rows
+    fn level_text(rows: &[&str]) -> String {
+        let rows: Vec<String> = rows
+            .iter()
+            .map(|r| r.chars().map(|c| format!("{} ", c)).collect())
+            .collect();
+        format!("Level 1-1\nPassword: AAAA\nTime: 1'00\"\n\n{}\n", rows.join("\n"))
+    }
+
+    #[test]
+    fn the_cursor_starts_where_marked() {
+        let text = level_text(&["-----", "-@  -", "- G -", "-----"]);
+        assert_eq!(parse_level(&text, 1).unwrap().start, (1, 1));
+    }
+
+    #[test]
+    fn unknown_tiles_are_errors() {
+        let text = level_text(&["-----", "-G? -", "- G -", "-----"]);
+        assert!(parse_level(&text, 1).is_ok());
+        let text = level_text(&["-----", "-G3 -", "- G -", "-----"]);
+        assert_eq!(
+            parse_level(&text, 1).err().unwrap(),
+            "unknown tile '3' at 2,1"
+        );
+    }
+
+    #[test]
+    fn unmarked_starts_are_the_nearest_empty_interior_cell() {
+        // the middle of the map is a wall
+        let text = level_text(&["------", "-G   -", "--- --", "-  G--", "------"]);
+        assert_eq!(parse_level(&text, 1).unwrap().start, (2, 1));
+        let text = level_text(&["-----", "-GEG-", "-----"]);
+        assert!(parse_level(&text, 1).is_err());
+        let text = level_text(&["-----", "-G  -", "-----", "  @  "]);
+        assert!(parse_level(&text, 1).is_err());
+        // too narrow to have a middle
+        assert!(parse_level(&level_text(&["-", "-"]), 1).is_err());
+    }
+
+    #[test]
+    fn both_encodings_round_trip() {
+        for n in [1, 22, 56, 160] {
+            let text = std::fs::read_to_string(format!("levels/{}.txt", n)).unwrap();
+            let spaced = format_level(&text, None).unwrap();
+            let compact = format_level(&spaced, Some(Encoding::Compact)).unwrap();
+            assert!(compact.lines().nth(3) == Some("Grid: compact"));
+            assert_eq!(format_level(&compact, Some(Encoding::Spaced)).unwrap(), spaced);
+            assert_eq!(format_level(&spaced, None).unwrap(), spaced);
+
+            let positions = |text: &str| -> Vec<(char, Vec2)> {
+                let info = parse_level(text, n).unwrap();
+                info.tiles.iter().map(|t| (t.c, t.position)).collect()
+            };
+            assert_eq!(positions(&compact), positions(&text));
+        }
+    }
+
+    #[test]
+    fn shipped_levels_are_formatted() {
+        for n in 1..161 {
+            let text = std::fs::read_to_string(format!("levels/{}.txt", n)).unwrap();
+            assert_eq!(format_level(&text, None).unwrap(), text, "level {}", n);
+        }
+    }
+
+    #[test]
+    fn music_follows_the_world_unless_picked() {
+        let text = std::fs::read_to_string("levels/56.txt").unwrap();
+        let info = parse_level(&text, 56).unwrap();
+        assert_eq!((info.world, info.name.as_str()), (6, "6-6"));
+        assert_eq!(info.password, "J5ZC");
+        assert_eq!(info.music, Sounds::Music3);
+
+        let text = text + "\nMusic: 1\n";
+        assert_eq!(parse_level(&text, 56).unwrap().music, Sounds::Music1);
+        let formatted = format_level(&text, None).unwrap();
+        assert_eq!(LevelFile::parse(&formatted).unwrap().music, Some(1));
+        assert!(parse_level(&text.replace("Music: 1", "Music: 4"), 56).is_err());
+    }
+
+    #[test]
+    fn compact_maps_are_detected() {
+        let text = "Level 1-1\nPassword: AAAA\nTime: 1'00\"\n\n-----\n-G G-\n-----\n";
+        let file = LevelFile::parse(text).unwrap();
+        assert_eq!(file.encoding, Encoding::Compact);
+        assert_eq!(file.rows[1], "-G G-");
+        assert!(LevelFile::parse(&text.replace("\n\n", "\nGrid: hex\n")).is_err());
+    }
+
+    #[test]
+    fn interiors_hold_every_tile_and_stay_inside_the_walls() {
+        for n in 1..161 {
+            let info = load_level(Path::new("levels"), n).unwrap();
+            let inside = |t: &Tile| info.blanks.iter().any(|b| b.position == t.position);
+            for tile in info.tiles.iter().filter(|t| t.is_playable()) {
+                assert!(inside(tile), "level {}: {:?} is outside", n, tile.position);
+            }
+            let (right, bottom) = (info.width - 1, info.height - 1);
+            for blank in info.blanks.iter() {
+                let (x, y) = (blank.position.x / TILE_WIDTH, blank.position.y / TILE_HEIGHT);
+                assert!(
+                    x > 0. && y > 0. && x < right as f32 && y < bottom as f32,
+                    "level {}: interior leaks at {},{}",
+                    n,
+                    x,
+                    y
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,450 @@
+use macroquad::prelude::*;
+
+use super::sound::{Mixer, Sounds};
+
+/// What a menu reacts to, read from the keyboard by `Menu::update`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+impl MenuInput {
+    const KEYS: [(KeyCode, MenuInput); 6] = [
+        (KeyCode::Up, MenuInput::Up),
+        (KeyCode::Down, MenuInput::Down),
+        (KeyCode::Left, MenuInput::Left),
+        (KeyCode::Right, MenuInput::Right),
+        (KeyCode::Enter, MenuInput::Select),
+        (KeyCode::Escape, MenuInput::Back),
+    ];
+}
+
+/// What happened to the menu after an input
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MenuEvent<T> {
+    /// The selection moved to another item
+    Moved,
+    /// A button was chosen
+    Selected(T),
+    /// A toggle, slider or choice took a new value
+    Changed(T),
+    /// A submenu was opened
+    Opened,
+    /// A submenu was closed, going back to its parent
+    Closed,
+    /// Back was chosen at the top of the menu
+    Back,
+}
+
+/// How an item looks and what it does
+pub enum Widget<T> {
+    Button,
+    /// A button that shows a value next to it, such as a key binding
+    Setting(String),
+    Toggle(bool),
+    Slider {
+        value: f32,
+        min: f32,
+        max: f32,
+        step: f32,
+        show: fn(f32) -> String,
+    },
+    Choice {
+        options: Vec<String>,
+        index: usize,
+    },
+    Submenu(Vec<MenuItem<T>>),
+    /// Closes the submenu it's in, or the whole menu at the top
+    Back,
+}
+
+pub struct MenuItem<T> {
+    pub id: T,
+    pub label: String,
+    pub widget: Widget<T>,
+}
+
+impl<T> MenuItem<T> {
+    fn new(id: T, label: &str, widget: Widget<T>) -> Self {
+        MenuItem {
+            id,
+            label: label.to_string(),
+            widget,
+        }
+    }
+
+    pub fn button(id: T, label: &str) -> Self {
+        MenuItem::new(id, label, Widget::Button)
+    }
+
+    pub fn setting(id: T, label: &str, value: &str) -> Self {
+        MenuItem::new(id, label, Widget::Setting(value.to_string()))
+    }
+
+    pub fn toggle(id: T, label: &str, on: bool) -> Self {
+        MenuItem::new(id, label, Widget::Toggle(on))
+    }
+
+    pub fn slider(
+        id: T,
+        label: &str,
+        value: f32,
+        (min, max, step): (f32, f32, f32),
+        show: fn(f32) -> String,
+    ) -> Self {
+        let widget = Widget::Slider {
+            value,
+            min,
+            max,
+            step,
+            show,
+        };
+        MenuItem::new(id, label, widget)
+    }
+
+    pub fn choice(id: T, label: &str, options: &[&str], index: usize) -> Self {
+        let options = options.iter().map(|o| o.to_string()).collect();
+        MenuItem::new(id, label, Widget::Choice { options, index })
+    }
+
+    pub fn submenu(id: T, label: &str, items: Vec<MenuItem<T>>) -> Self {
+        MenuItem::new(id, label, Widget::Submenu(items))
+    }
+
+    pub fn back(id: T, label: &str) -> Self {
+        MenuItem::new(id, label, Widget::Back)
+    }
+
+    /// The text shown to the right of the label
+    fn value(&self) -> String {
+        match &self.widget {
+            Widget::Setting(value) => value.clone(),
+            Widget::Toggle(true) => "ON".to_string(),
+            Widget::Toggle(false) => "OFF".to_string(),
+            Widget::Slider { value, show, .. } => show(*value),
+            Widget::Choice { options, index } => options[*index].clone(),
+            Widget::Button | Widget::Submenu(_) | Widget::Back => String::new(),
+        }
+    }
+
+    /// Moves a toggle, slider or choice one step, returns whether it changed
+    fn step(&mut self, up: bool) -> bool {
+        match &mut self.widget {
+            Widget::Toggle(on) => *on = !*on,
+            Widget::Slider {
+                value,
+                min,
+                max,
+                step,
+                ..
+            } => {
+                let steps = ((*value - *min) / *step).round() + if up { 1. } else { -1. };
+                let new = (*min + steps * *step).clamp(*min, *max);
+                if new == *value {
+                    return false;
+                }
+                *value = new;
+            }
+            Widget::Choice { options, index } => {
+                let n = *index + if up { 1 } else { options.len() - 1 };
+                *index = n % options.len();
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+fn find<T: Copy + PartialEq>(items: &[MenuItem<T>], id: T) -> Option<&MenuItem<T>> {
+    items.iter().find_map(|item| match &item.widget {
+        _ if item.id == id => Some(item),
+        Widget::Submenu(sub) => find(sub, id),
+        _ => None,
+    })
+}
+
+fn find_mut<T: Copy + PartialEq>(items: &mut [MenuItem<T>], id: T) -> Option<&mut MenuItem<T>> {
+    let n = items
+        .iter()
+        .position(|item| find(std::slice::from_ref(item), id).is_some())?;
+    let item = &mut items[n];
+    if item.id == id {
+        return Some(item);
+    }
+    match &mut item.widget {
+        Widget::Submenu(sub) => find_mut(sub, id),
+        _ => None,
+    }
+}
+
+/// A list of items drawn centred on the 320x200 screen, with submenus
+/// opening in place of their parent
+pub struct Menu<T> {
+    pub title: String,
+    items: Vec<MenuItem<T>>,
+    path: Vec<usize>, // selection in each open submenu's parent, outermost first
+    selection: usize,
+    pub top: f32,       // where the first line goes
+    pub spacing: f32,   // distance between lines
+    pub font_size: f32, // in screen units
+    pub columns: usize, // items in a row, Left and Right move along it when more than one
+}
+
+impl<T: Copy + PartialEq> Menu<T> {
+    pub fn new(title: &str, items: Vec<MenuItem<T>>) -> Self {
+        Menu {
+            title: title.to_string(),
+            items,
+            path: vec![],
+            selection: 0,
+            top: 40.,
+            spacing: 12.,
+            font_size: 8.,
+            columns: 1,
+        }
+    }
+
+    /// The items of the innermost open submenu
+    fn current(&self) -> &[MenuItem<T>] {
+        let mut items = &self.items;
+        for n in &self.path {
+            match &items[*n].widget {
+                Widget::Submenu(sub) => items = sub,
+                _ => unreachable!(),
+            }
+        }
+        items
+    }
+
+    fn current_mut(&mut self) -> &mut Vec<MenuItem<T>> {
+        let mut items = &mut self.items;
+        for n in &self.path {
+            match &mut items[*n].widget {
+                Widget::Submenu(sub) => items = sub,
+                _ => unreachable!(),
+            }
+        }
+        items
+    }
+
+    pub fn selected(&self) -> &MenuItem<T> {
+        &self.current()[self.selection]
+    }
+
+    /// Finds an item anywhere in the menu
+    pub fn item(&self, id: T) -> Option<&MenuItem<T>> {
+        find(&self.items, id)
+    }
+
+    pub fn item_mut(&mut self, id: T) -> Option<&mut MenuItem<T>> {
+        find_mut(&mut self.items, id)
+    }
+
+    pub fn selection(&self) -> usize {
+        self.selection
+    }
+
+    fn move_by(&mut self, step: usize) -> Option<MenuEvent<T>> {
+        self.selection = (self.selection + step) % self.current().len();
+        Some(MenuEvent::Moved)
+    }
+
+    /// Reacts to one input
+    pub fn navigate(&mut self, input: MenuInput) -> Option<MenuEvent<T>> {
+        let len = self.current().len();
+        if len == 0 {
+            return None;
+        }
+        match input {
+            MenuInput::Down if len > 1 => self.move_by(self.columns),
+            MenuInput::Up if len > 1 => self.move_by(len - self.columns % len),
+            MenuInput::Right if self.columns > 1 => self.move_by(1),
+            MenuInput::Left if self.columns > 1 => self.move_by(len - 1),
+            MenuInput::Left | MenuInput::Right => {
+                let selection = self.selection;
+                let item = &mut self.current_mut()[selection];
+                if item.step(input == MenuInput::Right) {
+                    Some(MenuEvent::Changed(item.id))
+                } else {
+                    None
+                }
+            }
+            MenuInput::Select => {
+                let selection = self.selection;
+                let item = &mut self.current_mut()[selection];
+                match item.widget {
+                    Widget::Button | Widget::Setting(_) => Some(MenuEvent::Selected(item.id)),
+                    Widget::Toggle(_) | Widget::Choice { .. } => {
+                        item.step(true);
+                        Some(MenuEvent::Changed(item.id))
+                    }
+                    Widget::Slider { .. } => None,
+                    Widget::Submenu(_) => {
+                        self.path.push(selection);
+                        self.selection = 0;
+                        Some(MenuEvent::Opened)
+                    }
+                    Widget::Back => self.navigate(MenuInput::Back),
+                }
+            }
+            MenuInput::Back => match self.path.pop() {
+                Some(parent) => {
+                    self.selection = parent;
+                    Some(MenuEvent::Closed)
+                }
+                None => Some(MenuEvent::Back),
+            },
+            _ => None,
+        }
+    }
+
+    /// Reads the keyboard and plays the menu sounds, call once per frame
+    pub async fn update(&mut self, mixer: &mut Mixer) -> Option<MenuEvent<T>> {
+        let (_, input) = MenuInput::KEYS
+            .iter()
+            .find(|(key, _)| is_key_pressed(*key))?;
+        let event = self.navigate(*input)?;
+        match event {
+            MenuEvent::Moved | MenuEvent::Changed(_) => mixer.play_sound(Sounds::MenuMove).await,
+            _ => mixer.play_sound(Sounds::MenuSelect).await,
+        }
+        Some(event)
+    }
+
+    fn text_params(&self, font: Font, color: Color) -> TextParams {
+        let (fs, fc, fa) = camera_font_scale(self.font_size);
+        TextParams {
+            font,
+            font_size: fs,
+            font_scale: fc,
+            font_scale_aspect: fa,
+            color,
+        }
+    }
+
+    fn text_width(&self, font: Font, text: &str) -> f32 {
+        let tp = self.text_params(font, WHITE);
+        measure_text(text, Some(font), tp.font_size, tp.font_scale).width * tp.font_scale_aspect
+    }
+
+    /// Draws the menu centred on a 320 wide camera. Labels without values are
+    /// centred one by one, otherwise labels and values line up in two columns
+    /// that are centred together.
+    pub fn draw(&self, font: Font) {
+        let items = self.current();
+        let mut y = self.top;
+        if !self.title.is_empty() {
+            let x = (320. - self.text_width(font, &self.title)) / 2.;
+            draw_text_ex(&self.title, x, y, self.text_params(font, GREEN));
+            y += self.spacing * 1.5;
+        }
+
+        let values: Vec<String> = items.iter().map(|item| item.value()).collect();
+        let widest = |texts: &mut dyn Iterator<Item = &String>| {
+            texts
+                .map(|text| self.text_width(font, text))
+                .fold(0., f32::max)
+        };
+        let label_width = widest(&mut items.iter().map(|item| &item.label));
+        let value_width = widest(&mut values.iter());
+        let gap = self.font_size * 2.;
+        let left = (320. - label_width - gap - value_width) / 2.;
+
+        for (n, (item, value)) in items.iter().zip(&values).enumerate() {
+            let color = if n == self.selection {
+                WHITE
+            } else {
+                LIGHTGRAY
+            };
+            let tp = self.text_params(font, color);
+            if value_width == 0. {
+                let x = (320. - self.text_width(font, &item.label)) / 2.;
+                draw_text_ex(&item.label, x, y, tp);
+            } else {
+                draw_text_ex(&item.label, left, y, tp);
+                draw_text_ex(value, left + label_width + gap, y, tp);
+            }
+            y += self.spacing;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percent(value: f32) -> String {
+        format!("{}%", (value * 100.).round())
+    }
+
+    fn menu() -> Menu<u8> {
+        Menu::new(
+            "TEST",
+            vec![
+                MenuItem::button(0, "PLAY"),
+                MenuItem::submenu(
+                    1,
+                    "SOUND",
+                    vec![
+                        MenuItem::slider(2, "VOLUME", 0.9, (0., 1., 0.1), percent),
+                        MenuItem::toggle(3, "MUTE", false),
+                        MenuItem::back(4, "BACK"),
+                    ],
+                ),
+                MenuItem::button(5, "QUIT"),
+            ],
+        )
+    }
+
+    #[test]
+    fn submenus_open_and_go_back() {
+        let mut menu = menu();
+        assert_eq!(menu.navigate(MenuInput::Up), Some(MenuEvent::Moved));
+        assert_eq!(
+            menu.navigate(MenuInput::Select),
+            Some(MenuEvent::Selected(5))
+        );
+        menu.navigate(MenuInput::Up);
+        assert_eq!(menu.navigate(MenuInput::Select), Some(MenuEvent::Opened));
+        assert_eq!(menu.selected().id, 2);
+        menu.navigate(MenuInput::Up);
+        assert_eq!(menu.navigate(MenuInput::Select), Some(MenuEvent::Closed));
+        assert_eq!(menu.selected().id, 1);
+        assert_eq!(menu.navigate(MenuInput::Back), Some(MenuEvent::Back));
+    }
+
+    #[test]
+    fn sliders_stop_at_their_ends() {
+        let mut menu = menu();
+        menu.navigate(MenuInput::Down);
+        menu.navigate(MenuInput::Select);
+        assert_eq!(menu.navigate(MenuInput::Right), Some(MenuEvent::Changed(2)));
+        assert_eq!(menu.navigate(MenuInput::Right), None);
+        assert_eq!(menu.selected().value(), "100%");
+        menu.navigate(MenuInput::Down);
+        menu.navigate(MenuInput::Left);
+        assert!(matches!(menu.item(3).unwrap().widget, Widget::Toggle(true)));
+    }
+
+    #[test]
+    fn grids_move_by_rows_and_columns() {
+        let items = (0..10).map(|n| MenuItem::button(n, "")).collect();
+        let mut menu = Menu::new("", items);
+        menu.columns = 5;
+        menu.navigate(MenuInput::Right);
+        menu.navigate(MenuInput::Down);
+        assert_eq!(menu.selected().id, 6);
+        menu.navigate(MenuInput::Down);
+        assert_eq!(menu.selected().id, 1);
+        menu.navigate(MenuInput::Left);
+        menu.navigate(MenuInput::Left);
+        assert_eq!(menu.selected().id, 9);
+        menu.navigate(MenuInput::Up);
+        assert_eq!(menu.selected().id, 4);
+    }
+}
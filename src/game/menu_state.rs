@@ -2,45 +2,64 @@
 
 use async_trait::async_trait;
 use macroquad::prelude::*;
-use crate::game::sound::Mixer;
-use super::states::{Playable, StateType};
+use super::{
+    assets::Assets,
+    menu::{Menu, MenuEvent, MenuItem},
+    states::{GameContext, Playable, StateType, Transition},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Choice {
+    NewGame,
+    LevelSelect,
+    Options,
+    Instructions,
+    Quit,
+}
 
 pub struct MenuState {
     font: Font,
-    selection: u8,
+    background: Texture2D,
+    menu: Menu<Choice>,
 }
 
 impl MenuState {
-    pub async fn new() -> Self {
-        let font = load_ttf_font("Nintendo-NES-Font.ttf").await.unwrap();
-
-        MenuState { selection: 0, font }
-    }
-    fn get_text_params(&self, selection: u8) -> TextParams {
-        let (fs, fc, fa) = camera_font_scale(8.);
-
-        let mut tp = TextParams {
-            font: self.font,
-            font_size: fs,
-            font_scale: fc,
-            font_scale_aspect: fa,
-            color: LIGHTGRAY,
-        };
-
-        if self.selection == selection {
-            tp.color = WHITE;
+    pub fn new(assets: &Assets) -> Self {
+        let mut menu = Menu::new(
+            "",
+            vec![
+                MenuItem::button(Choice::NewGame, "NEW GAME"),
+                MenuItem::button(Choice::LevelSelect, "LEVEL SELECT"),
+                MenuItem::button(Choice::Options, "OPTIONS"),
+                MenuItem::button(Choice::Instructions, "INSTRUCTIONS"),
+                MenuItem::button(Choice::Quit, "QUIT"),
+            ],
+        );
+        menu.top = 92.;
+        menu.spacing = 12.;
+        MenuState {
+            font: assets.font,
+            background: assets.menu_bg,
+            menu,
         }
-        tp
     }
 }
 #[async_trait]
 impl Playable for MenuState {
-    async fn run(&mut self, mixer: &mut Mixer) -> StateType {
-        let desired_ratio = 320. / 200. as f32;
+    async fn enter(&mut self, ctx: &mut GameContext) {
+        ctx.mixer.stop_music();
+    }
+
+    async fn resume(&mut self, ctx: &mut GameContext) {
+        println!("Jumping to menu");
+        ctx.mixer.stop_music();
+    }
+
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition {
+        let desired_ratio = 320. / 200.;
 
-        let background = load_texture("img/menu_bg.png").await.unwrap();
-        background.set_filter(FilterMode::Nearest);
         loop {
+            ctx.update();
             let physical_ratio = screen_width() / screen_height();
 
             let mut w = 320.;
@@ -74,34 +93,27 @@ impl Playable for MenuState {
                 flip_y: false,
             };
 
-            draw_texture_ex(background, 0., 0., WHITE, bg_params);
+            draw_texture_ex(self.background, 0., 0., WHITE, bg_params);
 
             // draw_text_ex("PUZZNIC!", 130., 80., tp);
-            draw_text_ex("NEW GAME", 140., 100., self.get_text_params(0));
-            draw_text_ex("INSTRUCTIONS", 120., 120., self.get_text_params(1));
-            draw_text_ex("QUIT", 155., 140., self.get_text_params(2));
-            next_frame().await;
-            if is_key_pressed(KeyCode::Down) {
-                self.selection += 1;
-                if self.selection == 3 {
-                    self.selection = 0;
+            self.menu.draw(self.font);
+            match self.menu.update(&mut ctx.mixer).await {
+                Some(MenuEvent::Selected(Choice::NewGame)) => {
+                    return Transition::Push(StateType::Playing(1))
                 }
-            }
-            if is_key_pressed(KeyCode::Up) {
-                if self.selection == 0 {
-                    self.selection = 2;
-                } else {
-                    self.selection -= 1;
+                Some(MenuEvent::Selected(Choice::LevelSelect)) => {
+                    return Transition::Push(StateType::LevelSelect)
                 }
-            }
-            if is_key_pressed(KeyCode::Enter) {
-                match self.selection {
-                    0 => return StateType::Playing(1),
-                    1 => return StateType::Help,
-                    2 => return StateType::Quit,
-                    _ => {}
+                Some(MenuEvent::Selected(Choice::Options)) => {
+                    return Transition::Push(StateType::Options)
+                }
+                Some(MenuEvent::Selected(Choice::Instructions)) => {
+                    return Transition::Push(StateType::Help)
                 }
+                Some(MenuEvent::Selected(Choice::Quit)) => return Transition::Quit,
+                _ => {}
             }
+            ctx.next_frame().await;
         }
 
     }
@@ -1,7 +1,20 @@
+pub mod args;
+pub mod assets;
+pub mod config;
 pub(crate) mod game_logic;
+pub mod grid;
+pub mod help_state;
+pub mod input;
+pub mod level_select_state;
 pub mod levels;
+pub mod menu;
 pub mod menu_state;
+pub mod options_state;
 pub mod playing_state;
-pub(crate) mod sound;
+pub mod records;
+pub mod results_state;
+pub mod sound;
 pub mod states;
-pub mod tile;
\ No newline at end of file
+pub mod tile;
+pub mod tile_kind;
+pub mod transition;
\ No newline at end of file
@@ -0,0 +1,264 @@
+use async_trait::async_trait;
+use macroquad::{prelude::*, window::get_internal_gl};
+
+use super::{
+    assets::Assets,
+    config::{Config, GameSpeed, MAX_SCALE},
+    input::{key_name, Action, BINDABLE_KEYS},
+    menu::{Menu, MenuEvent, MenuItem, Widget},
+    states::{GameContext, Playable, Transition},
+};
+
+/// Seconds a buffered drag waits when the drag buffer is on
+const DRAG_BUFFER: f64 = 0.5;
+
+/// One line of the options screen
+#[derive(Clone, Copy, PartialEq)]
+enum Item {
+    Sound,
+    MusicVolume,
+    SfxVolume,
+    Display,
+    Screen,
+    Scale,
+    Game,
+    GameSpeed,
+    Drag,
+    DragBuffer,
+    Controls,
+    RepeatDelay,
+    RepeatInterval,
+    Key(Action),
+    Back,
+}
+
+fn percent(volume: f32) -> String {
+    format!("{}%", (volume * 100.).round())
+}
+
+fn times(scale: f32) -> String {
+    format!("{}X", scale)
+}
+
+fn millis(seconds: f32) -> String {
+    format!("{}MS", (seconds * 1000.).round())
+}
+
+/// A slider's seconds, without the float error its steps add up
+fn seconds(value: f32) -> f64 {
+    (value as f64 * 1000.).round() / 1000.
+}
+
+fn menu(config: &Config) -> Menu<Item> {
+    let speeds: Vec<&str> = GameSpeed::ALL.iter().map(|s| s.name()).collect();
+    let mut controls = vec![
+        MenuItem::slider(
+            Item::RepeatDelay,
+            "REPEAT DELAY",
+            config.repeat_delay as f32,
+            (0.1, 0.5, 0.05),
+            millis,
+        ),
+        MenuItem::slider(
+            Item::RepeatInterval,
+            "REPEAT RATE",
+            config.repeat_interval as f32,
+            (0.02, 0.2, 0.02),
+            millis,
+        ),
+    ];
+    controls.extend(
+        Action::ALL
+            .iter()
+            .map(|a| MenuItem::setting(Item::Key(*a), a.name(), &key_label(config, *a))),
+    );
+    controls.push(MenuItem::back(Item::Back, "BACK"));
+    let volumes = (0., 1., 0.1);
+
+    let mut menu = Menu::new(
+        "OPTIONS",
+        vec![
+            MenuItem::submenu(
+                Item::Sound,
+                "SOUND",
+                vec![
+                    MenuItem::slider(
+                        Item::MusicVolume,
+                        "MUSIC",
+                        config.music_volume,
+                        volumes,
+                        percent,
+                    ),
+                    MenuItem::slider(
+                        Item::SfxVolume,
+                        "EFFECTS",
+                        config.sfx_volume,
+                        volumes,
+                        percent,
+                    ),
+                    MenuItem::back(Item::Back, "BACK"),
+                ],
+            ),
+            MenuItem::submenu(
+                Item::Display,
+                "DISPLAY",
+                vec![
+                    MenuItem::choice(
+                        Item::Screen,
+                        "SCREEN",
+                        &["WINDOW", "FULLSCREEN"],
+                        config.fullscreen as usize,
+                    ),
+                    MenuItem::slider(
+                        Item::Scale,
+                        "WINDOW SIZE",
+                        config.scale as f32,
+                        (1., MAX_SCALE as f32, 1.),
+                        times,
+                    ),
+                    MenuItem::back(Item::Back, "BACK"),
+                ],
+            ),
+            MenuItem::submenu(
+                Item::Game,
+                "GAME",
+                vec![
+                    MenuItem::choice(
+                        Item::GameSpeed,
+                        "SPEED",
+                        &speeds,
+                        config.game_speed as usize,
+                    ),
+                    MenuItem::choice(
+                        Item::Drag,
+                        "DRAG",
+                        &["ONE CELL", "CONTINUOUS"],
+                        config.continuous_drag as usize,
+                    ),
+                    MenuItem::toggle(Item::DragBuffer, "DRAG BUFFER", config.input_buffer > 0.),
+                    MenuItem::back(Item::Back, "BACK"),
+                ],
+            ),
+            MenuItem::submenu(Item::Controls, "CONTROLS", controls),
+            MenuItem::back(Item::Back, "BACK"),
+        ],
+    );
+    menu.top = 30.;
+    menu.spacing = 11.;
+    menu.font_size = 6.;
+    menu
+}
+
+fn key_label(config: &Config, action: Action) -> String {
+    key_name(config.keys.key(action)).to_uppercase()
+}
+
+/// Copies a changed item's value into the settings
+fn store(item: &MenuItem<Item>, config: &mut Config) {
+    match (item.id, &item.widget) {
+        (Item::MusicVolume, Widget::Slider { value, .. }) => config.music_volume = *value,
+        (Item::SfxVolume, Widget::Slider { value, .. }) => config.sfx_volume = *value,
+        (Item::Screen, Widget::Choice { index, .. }) => config.fullscreen = *index == 1,
+        (Item::Scale, Widget::Slider { value, .. }) => config.scale = *value as u32,
+        (Item::GameSpeed, Widget::Choice { index, .. }) => {
+            config.game_speed = GameSpeed::ALL[*index]
+        }
+        (Item::Drag, Widget::Choice { index, .. }) => config.continuous_drag = *index == 1,
+        (Item::DragBuffer, Widget::Toggle(true)) => config.input_buffer = DRAG_BUFFER,
+        (Item::DragBuffer, Widget::Toggle(false)) => config.input_buffer = 0.,
+        (Item::RepeatDelay, Widget::Slider { value, .. }) => config.repeat_delay = seconds(*value),
+        (Item::RepeatInterval, Widget::Slider { value, .. }) => {
+            config.repeat_interval = seconds(*value)
+        }
+        _ => {}
+    }
+}
+
+/// Switches to fullscreen or back and resizes the window. miniquad can't do
+/// either on Linux or macOS while the game runs, there it shows on next start.
+pub fn apply_display(config: &Config) {
+    let gl = unsafe { get_internal_gl() };
+    gl.quad_context.set_fullscreen(config.fullscreen);
+    if !config.fullscreen {
+        gl.quad_context
+            .set_window_size(320 * config.scale, 200 * config.scale);
+    }
+}
+
+pub struct OptionsState {
+    font: Font,
+    menu: Menu<Item>,
+    rebinding: Option<Action>, // waiting for the key to bind to this action
+}
+
+impl OptionsState {
+    pub fn new(assets: &Assets, config: &Config) -> Self {
+        OptionsState {
+            font: assets.font,
+            menu: menu(config),
+            rebinding: None,
+        }
+    }
+
+    /// Shows every key again, binding one key can move another
+    fn show_keys(&mut self, config: &Config) {
+        for action in Action::ALL.iter() {
+            if let Some(item) = self.menu.item_mut(Item::Key(*action)) {
+                item.widget = Widget::Setting(key_label(config, *action));
+            }
+        }
+    }
+
+    /// Takes the next key pressed for the action being rebound
+    fn handle_rebinding(&mut self, action: Action, ctx: &mut GameContext) {
+        match get_last_key_pressed() {
+            Some(KeyCode::Escape) => {}
+            Some(key) if BINDABLE_KEYS.contains(&key) => ctx.config.keys.bind(action, key),
+            _ => return,
+        }
+        self.rebinding = None;
+        self.show_keys(&ctx.config);
+    }
+}
+
+#[async_trait]
+impl Playable for OptionsState {
+    async fn exit(&mut self, ctx: &mut GameContext) {
+        ctx.save_config();
+    }
+
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition {
+        loop {
+            ctx.update();
+            set_camera(&Camera2D::from_display_rect(Rect::new(0., 0., 320., 200.)));
+            clear_background(BLACK);
+            self.menu.draw(self.font);
+
+            if let Some(action) = self.rebinding {
+                self.handle_rebinding(action, ctx);
+                ctx.next_frame().await;
+                continue;
+            }
+            match self.menu.update(&mut ctx.mixer).await {
+                Some(MenuEvent::Changed(id)) => {
+                    if let Some(item) = self.menu.item(id) {
+                        store(item, &mut ctx.config);
+                    }
+                    ctx.mixer.apply_config(&ctx.config);
+                    if id == Item::Screen || id == Item::Scale {
+                        apply_display(&ctx.config);
+                    }
+                }
+                Some(MenuEvent::Selected(Item::Key(action))) => {
+                    self.rebinding = Some(action);
+                    if let Some(item) = self.menu.item_mut(Item::Key(action)) {
+                        item.widget = Widget::Setting("PRESS A KEY".to_string());
+                    }
+                }
+                Some(MenuEvent::Back) => return Transition::Pop,
+                _ => {}
+            }
+            ctx.next_frame().await;
+        }
+    }
+}
@@ -1,13 +1,15 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::collections::BTreeMap;
 
 use async_trait::async_trait;
 use macroquad::prelude::*;
-use crate::game::tile::TileChange;
 
 use super::{
     game_logic::PlayingState,
+    input::Action,
+    menu::{Menu, MenuEvent, MenuItem},
+    records::clock,
     sound::{self, Mixer},
-    states::{Playable, StateType},
+    states::{GameContext, Playable, StateType, Transition},
 };
 
 // use self::{sound::Mixer, tile::Tile, tile::TileChange};
@@ -16,6 +18,31 @@ pub const TILE_WIDTH: f32 = 16f32;
 pub const TILE_HEIGHT: f32 = 16f32;
 pub const SPEED: f32 = 1.;
 
+/// What the pause menu offers
+#[derive(Clone, Copy, PartialEq)]
+pub enum PauseChoice {
+    Resume,
+    Restart,
+    Options,
+    Quit,
+}
+
+pub fn pause_menu() -> Menu<PauseChoice> {
+    let mut menu = Menu::new(
+        "PAUSED",
+        vec![
+            MenuItem::button(PauseChoice::Resume, "RESUME"),
+            MenuItem::button(PauseChoice::Restart, "RESTART"),
+            MenuItem::button(PauseChoice::Options, "OPTIONS"),
+            MenuItem::button(PauseChoice::Quit, "QUIT LEVEL"),
+        ],
+    );
+    menu.top = 70.;
+    menu.font_size = 6.;
+    menu
+}
+
+#[derive(Clone)]
 pub struct Player {
     pub position: (usize, usize),
 }
@@ -38,6 +65,35 @@ pub fn handle_draw_player(level: &mut PlayingState) {
     draw_rectangle_lines(x, y, TILE_WIDTH * 1.0, TILE_HEIGHT * 1.0, 2., RED);
 }
 
+/// While grabbing, shows where the tile would land if dragged either way,
+/// and which tiles that landing would clear
+pub fn handle_draw_preview(level: &mut PlayingState) {
+    if !level.dragging || !level.is_stable() {
+        level.preview = None;
+        return;
+    }
+    level.update_preview();
+    let preview = match &level.preview {
+        Some(preview) => preview,
+        None => return,
+    };
+    let (x, y) = (level.offset_x, level.offset_y);
+    for landing in &preview.landings {
+        let mut ghost = landing.kind.tint;
+        ghost.a = 0.4;
+        draw_texture_ex(
+            level.texture_map,
+            landing.position.x + x,
+            landing.position.y + y,
+            ghost,
+            level.get_tile_texture_params(landing.kind),
+        );
+        for position in &landing.matches {
+            draw_rectangle_lines(position.x + x, position.y + y, TILE_WIDTH, TILE_HEIGHT, 1., YELLOW);
+        }
+    }
+}
+
 pub fn handle_draw_map(level: &mut PlayingState) -> bool {
     // draw a grey background
 
@@ -57,7 +113,7 @@ pub fn handle_draw_map(level: &mut PlayingState) -> bool {
     //      for x in 0..dimensions.0 {
     for tile in &level.map {
         // let tile = level.get_tile_at(x, y);
-        if tile.is_playable() {
+        if tile.kind.matchable {
             playable_pieces += 1;
         }
         if tile.fade_step % 4 == 0 {
@@ -65,8 +121,8 @@ pub fn handle_draw_map(level: &mut PlayingState) -> bool {
                 level.texture_map,
                 tile.position.x + level.offset_x,
                 tile.position.y + level.offset_y,
-                WHITE,
-                level.get_tile_texture_params(tile.c),
+                tile.kind.tint,
+                level.get_tile_texture_params(tile.kind),
             );
         }
     }
@@ -75,86 +131,127 @@ pub fn handle_draw_map(level: &mut PlayingState) -> bool {
 }
 
 pub async fn handle_move_tiles(level: &mut PlayingState, mixer: &mut Mixer) {
-    let changes = level.next_map(&level.map);
-    let mut drain: Vec<u32> = vec![];
-    level.fading_out = false;
-    for (index, tile_change) in &changes {
-        let t = level.map.get_mut(*index).unwrap();
-        match tile_change {
-            TileChange::Stop => {
-                t.velocity = Vec2::ZERO;
-                t.dragging_direction = None;
-            }
-            TileChange::Move => {
-                t.position = t.position + t.velocity;
-            }
-            TileChange::Jump(position) => {
-                t.position = *position;
-                t.velocity = Vec2::ZERO;
-                t.dragging_direction = None;
-            }
-            TileChange::Bounce => {
-                t.velocity = t.velocity * -1.;
-            }
-            TileChange::FadeOut => {
-                if t.fade_step == 0 {
-                    mixer.play_sound(sound::Sounds::Collided).await;
-                }
-                t.fade_step = t.fade_step + 1;
-                level.fading_out = true;
-                if t.fade_step >= 50 {
-                    drain.push(t.id);
-                }
-            }
-            TileChange::StartRiding(velocity) => {
-                t.riding = true;
-                t.velocity = *velocity;
-            }
-            TileChange::Fall => {
-                t.velocity = Vec2::new(0., SPEED);
-                t.riding = false;
-            }
+    for snd in level.update_tiles() {
+        mixer.play_sound(snd).await;
+    }
+}
+/// Actions moving the cursor, in the same order as `PlayingState::key_repeat`
+pub const CURSOR_ACTIONS: [Action; 4] = [Action::Left, Action::Right, Action::Up, Action::Down];
+
+/// Whether the nth cursor key fires this frame. Held keys auto-repeat,
+/// except while grabbing so a drag never goes further than asked for.
+fn cursor_key_fired(level: &mut PlayingState, n: usize, now: f64) -> bool {
+    let key = level.keys.key(CURSOR_ACTIONS[n]);
+    let pressed = is_key_pressed(key);
+    let down = is_key_down(key) && !is_key_down(level.keys.key(Action::Grab));
+    level.key_repeat[n].update(pressed, down, now)
+}
 
-            TileChange::VelocityUpdate(vec2) => {
-                t.velocity = *vec2;
-            }
-            TileChange::RidingFlag(flag) => {
-                t.riding = *flag;
-            }
+/// The keys the cursor is moved with, as read in one frame
+pub struct MoveKeys {
+    pub fired: [bool; 4], // which of the `CURSOR_ACTIONS` fire
+    pub grab_pressed: bool,
+    pub grab_released: bool,
+    pub grab_down: bool,
+    pub now: f64,
+}
+
+impl MoveKeys {
+    fn read(level: &mut PlayingState) -> MoveKeys {
+        let grab = level.keys.key(Action::Grab);
+        let now = get_time();
+        let mut fired = [false; 4];
+        for (n, fired) in fired.iter_mut().enumerate() {
+            *fired = cursor_key_fired(level, n, now);
+        }
+        MoveKeys {
+            fired,
+            grab_pressed: is_key_pressed(grab),
+            grab_released: is_key_released(grab),
+            grab_down: is_key_down(grab),
+            now,
         }
     }
-    level.map.retain(|e| !drain.contains(&e.id));
 }
-pub async fn handle_move_player(level: &mut PlayingState, mixer: &mut Mixer) {
-    if is_key_pressed(KeyCode::Left) && level.dragging_step == 0  {
-        level.move_player(Direction::Left, mixer).await;
-        if level.dragging {
-            level.dragging_step +=1;
-                    println!("{}", level.dragging_step);
-        }
 
+/// In continuous mode a tile can be slid any number of cells in one grab
+fn can_drag(level: &PlayingState) -> bool {
+    level.dragging_step == 0 || level.continuous_drag
+}
+
+/// Moves the cursor, counting a step of the grab only if a tile was pushed
+async fn drag(level: &mut PlayingState, direction: Direction, mixer: &mut Mixer) {
+    let moves = level.moves;
+    level.move_player(direction, mixer).await;
+    if level.moves > moves {
+        level.dragging_step += 1;
+    }
+}
 
+/// Moves the cursor sideways, dragging the tile under it if grab is held.
+/// Continuous drags asked for while the board is still moving wait in the
+/// input buffer. One cell drags can't wait, as the board stands still
+/// while grab is held, so they are pushed right away and play out on release.
+async fn handle_move_sideways(
+    level: &mut PlayingState,
+    direction: Direction,
+    keys: &MoveKeys,
+    mixer: &mut Mixer,
+) {
+    if level.dragging
+        && level.continuous_drag
+        && level.input_buffer.is_enabled()
+        && !level.is_stable()
+    {
+        level.input_buffer.push(direction, keys.now);
+    } else {
+        drag(level, direction, mixer).await;
     }
-    if is_key_pressed(KeyCode::Right) && level.dragging_step == 0 {
-        level.move_player(Direction::Right, mixer).await;
-        if level.dragging {
-        level.dragging_step +=1;
-            println!("{}", level.dragging_step);
+}
+
+pub async fn handle_move_player(level: &mut PlayingState, mixer: &mut Mixer) {
+    let keys = MoveKeys::read(level);
+    handle_move_keys(level, &keys, mixer).await;
+}
+
+/// Moves the cursor and drags tiles with the keys read in a frame
+pub async fn handle_move_keys(level: &mut PlayingState, keys: &MoveKeys, mixer: &mut Mixer) {
+    // Carry out a buffered drag once nothing is falling or fading any more,
+    // as long as the tile is still held
+    if level.dragging && can_drag(level) && level.is_stable() {
+        if let Some(direction) = level.input_buffer.pop(keys.now) {
+            drag(level, direction, mixer).await;
         }
     }
 
-    if is_key_pressed(KeyCode::Up) && !level.dragging {
+    let can_drag = can_drag(level);
+    if keys.fired[0] && can_drag {
+        handle_move_sideways(level, Direction::Left, keys, mixer).await;
+    }
+    if keys.fired[1] && can_drag {
+        handle_move_sideways(level, Direction::Right, keys, mixer).await;
+    }
+
+    if keys.fired[2] && !level.dragging {
         level.move_player(Direction::Up, mixer).await;
     }
 
-    if is_key_down(KeyCode::Space) {
+    if keys.grab_pressed {
+        mixer.play_sound(sound::Sounds::Grab).await;
+    }
+    if keys.grab_released {
+        mixer.play_sound(sound::Sounds::Release).await;
+    }
+    if keys.grab_down {
         level.dragging = true;
     } else {
         level.dragging = false;
         level.dragging_step =0;
+        // let go before the board settled, the waiting drags go with it
+        level.input_buffer.clear();
     }
 
-    if is_key_pressed(KeyCode::Down) {
+    if keys.fired[3] {
         level.move_player(Direction::Down, mixer).await;
     }
 }
@@ -170,28 +267,27 @@ pub fn draw_score(level: &mut PlayingState) {
         color: GREEN,
     };
 
-    draw_text_ex("SCORE: 0", 10., 13., tp);
+    draw_text_ex(format!("SCORE: {}", level.score).as_str(), 10., 13., tp);
 
-    draw_text_ex(format!("LEVEL: {}", level.level).as_str(), 10., 22., tp);
-    draw_text_ex(format!("TIME: {}", level.time).as_str(), 10., 31., tp);
+    draw_text_ex(format!("LEVEL: {}", level.name).as_str(), 10., 22., tp);
+    draw_text_ex(format!("TIME: {}", clock(level.time_left() as u32)).as_str(), 10., 31., tp);
 
     let mut text_y = 50.;
 
     // Generate tiles_remaining HashMap
-    let allowed = ['G', 'X', 'E', 'B', 'P', 'C', 'D'];
     let mut tiles_remaining = BTreeMap::new();
-    for t in level.map.iter().filter(|t| allowed.contains(&t.c)) {
-        let count = tiles_remaining.entry(t.c).or_insert(0);
-        *count += 1;
+    for t in level.map.iter().filter(|t| t.kind.matchable) {
+        let count = tiles_remaining.entry(t.c).or_insert((t.kind, 0));
+        count.1 += 1;
     }
     // Draw the numer of tiles remaining
-    for (c, num) in &tiles_remaining {
+    for (kind, num) in tiles_remaining.values() {
         draw_texture_ex(
             level.texture_map,
             50.,
             text_y,
-            WHITE,
-            level.get_tile_texture_params(*c),
+            kind.tint,
+            level.get_tile_texture_params(kind),
         );
         draw_text_ex(num.to_string().as_ref(), 50. + 24., text_y + 10., tp);
         text_y += 20.;
@@ -213,38 +309,70 @@ pub fn draw_score(level: &mut PlayingState) {
 }
 #[async_trait]
 impl Playable for PlayingState {
-    async fn run(&mut self, mixer: &mut Mixer) -> super::states::StateType {
-        let desired_ratio = 320. / 200.;
-//        let mut mixer = sound::Mixer::new().await;
+    async fn enter(&mut self, ctx: &mut GameContext) {
+        self.apply_config(&ctx.config);
+        self.score = ctx.session.score;
+        ctx.mixer.play_sound(sound::Sounds::LevelIntro).await;
+        // the level starts once the intro tune's length has gone by, a timer
+        // as macroquad can't tell when a sound has finished playing
+        self.intro_end = Some(get_time() + ctx.mixer.length(sound::Sounds::LevelIntro) as f64);
+    }
+
+    async fn resume(&mut self, ctx: &mut GameContext) {
+        self.apply_config(&ctx.config);
+    }
 
-        // stop all sounds
+    async fn exit(&mut self, ctx: &mut GameContext) {
+        ctx.session.score = self.score;
+        ctx.mixer.stop_music();
+    }
 
-        
-        mixer.play_sound(sound::Sounds::LevelIntro).await;
-        let t2 = std::time::SystemTime::now().checked_add(Duration::from_secs(3)).unwrap();
-        let mut ended = false;
-        loop {
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition {
+        let desired_ratio = 320. / 200.;
 
-            let now = std::time::SystemTime::now();
-            if !ended && now > t2 {
-               ended = true;
-               mixer.play_sound(sound::Sounds::Playing).await;
+        loop {
+            ctx.update();
+            let mixer = &mut ctx.mixer;
+            self.count_time(get_frame_time() as f64);
+            if let Some(intro_end) = self.intro_end {
+                if get_time() >= intro_end || is_key_pressed(KeyCode::Enter) {
+                    self.intro_end = None;
+                    mixer.play_sound(self.music).await;
+                }
             }
-            
-            if is_key_pressed(KeyCode::R) {
-                return StateType::Playing(self.level);
+
+            // left once this frame is drawn, for the curtain to close over it
+            let mut leave = None;
+            if is_key_pressed(self.keys.key(Action::Restart)) {
+                leave = Some(Transition::Replace(StateType::Playing(self.level)));
             }
 
             if self.exit_intent && is_key_pressed(KeyCode::Y) {
-                return StateType::Menu;
+                leave = Some(Transition::Pop);
             }
 
-            if is_key_pressed(KeyCode::Escape) {
+            if is_key_pressed(KeyCode::Escape) && !self.paused {
                 self.exit_intent = !self.exit_intent;
             }
 
-            if is_key_pressed(KeyCode::P) {
+            if is_key_pressed(self.keys.key(Action::Pause)) && !self.exit_intent {
                 self.paused = !self.paused;
+                self.pause_menu = pause_menu();
+                self.input_buffer.clear();
+            } else if self.paused {
+                match self.pause_menu.update(mixer).await {
+                    Some(MenuEvent::Selected(PauseChoice::Resume)) | Some(MenuEvent::Back) => {
+                        self.paused = false;
+                    }
+                    Some(MenuEvent::Selected(PauseChoice::Restart)) => {
+                        leave = Some(Transition::Replace(StateType::Playing(self.level)));
+                    }
+                    Some(MenuEvent::Selected(PauseChoice::Options)) => {
+                        leave = Some(Transition::Push(StateType::Options));
+                    }
+                    Some(MenuEvent::Selected(PauseChoice::Quit)) => leave = Some(Transition::Pop),
+                    _ => {}
+                }
             }
 
             let physical_ratio = screen_width() / screen_height();
@@ -270,21 +398,22 @@ impl Playable for PlayingState {
 
             draw_score(self);
             set_camera(&camera);
-            if !self.paused && !self.exit_intent {
+            if !self.paused && !self.exit_intent && self.intro_end.is_none() && leave.is_none() {
                 handle_move_player(self, mixer).await;
-                if !is_key_down(KeyCode::Space) {
+                if !is_key_down(self.keys.key(Action::Grab)) || self.continuous_drag {
                     handle_move_tiles(self, mixer).await;
                 }
             }
 
             if handle_draw_map(self) {
-                mixer.stop_music();
                 println!("Level completed!");
+                mixer.play_sound(sound::Sounds::LevelClear).await;
                 break;
             }
+            handle_draw_preview(self);
             handle_draw_player(self);
 
-            if self.paused {
+            if self.intro_end.is_some() {
                 let (fs, fc, fa) = camera_font_scale(6.);
 
                 let tp = TextParams {
@@ -294,7 +423,10 @@ impl Playable for PlayingState {
                     font_scale_aspect: fa,
                     color: GREEN,
                 };
-                draw_text_ex("PAUSED", 150., 100., tp);
+                draw_text_ex(format!("LEVEL {}", self.name).as_str(), 150., 100., tp);
+            }
+            if self.paused {
+                self.pause_menu.draw(self.font);
             }
             if self.exit_intent {
                 let (fs, fc, fa) = camera_font_scale(6.);
@@ -309,11 +441,13 @@ impl Playable for PlayingState {
                 draw_text_ex("EXIT GAME?", 150., 100., tp);
             }
 
-            
-            next_frame().await;
+            if let Some(transition) = leave {
+                return transition;
+            }
+            ctx.next_frame().await;
         }
 
-        StateType::Playing(self.level + 1)
+        Transition::Replace(StateType::Results(self.results()))
     }
 }
 
@@ -0,0 +1,138 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// File the best results are kept in, next to the settings
+pub const RECORDS_FILE: &str = "puzznic.rec";
+
+/// Best result on one level. The score and time can come from different
+/// attempts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Record {
+    pub score: u32,
+    pub time: u32, // seconds used
+}
+
+impl Record {
+    /// Whether this has a better score or time than `best`, or there's no
+    /// best yet
+    pub fn beats(&self, best: Option<Record>) -> bool {
+        best.map_or(true, |best| self.score > best.score || self.time < best.time)
+    }
+}
+
+/// Best results of every level cleared so far
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Records {
+    best: BTreeMap<usize, Record>,
+}
+
+impl Records {
+    /// Reads the records saved in `path`, starting afresh if there are none
+    pub fn load(path: &Path) -> Records {
+        match fs::read_to_string(path) {
+            Ok(text) => Records::from_text(&text),
+            Err(e) => {
+                println!("No records yet, cannot read {}: {}", path.display(), e);
+                Records::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        if let Err(e) = fs::write(path, self.to_text()) {
+            println!("Cannot save records to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Reads `level = score time` lines, ignoring blank lines and `#` comments
+    pub fn from_text(text: &str) -> Records {
+        let mut records = Records::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_record(line) {
+                Some((level, record)) => {
+                    records.best.insert(level, record);
+                }
+                None => println!("Ignoring record {:?}", line),
+            }
+        }
+        records
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec!["# Puzznic records: level = score seconds".to_string()];
+        for (level, record) in &self.best {
+            lines.push(format!("{} = {} {}", level, record.score, record.time));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn get(&self, level: usize) -> Option<Record> {
+        self.best.get(&level).copied()
+    }
+
+    /// Keeps whatever beats the best so far, returns the records it replaced,
+    /// `None` the first time a level is cleared
+    pub fn add(&mut self, level: usize, record: Record) -> Option<Record> {
+        let old = self.get(level);
+        let best = match old {
+            Some(old) => Record {
+                score: old.score.max(record.score),
+                time: old.time.min(record.time),
+            },
+            None => record,
+        };
+        self.best.insert(level, best);
+        old
+    }
+
+    /// The first level is always open, any other once the one before it is
+    /// cleared
+    pub fn unlocked(&self, level: usize) -> bool {
+        level <= 1 || self.best.contains_key(&(level - 1))
+    }
+}
+
+fn parse_record(line: &str) -> Option<(usize, Record)> {
+    let (level, values) = line.split_once('=')?;
+    let mut values = values.split_whitespace().map(str::parse);
+    let record = Record {
+        score: values.next()?.ok()?,
+        time: values.next()?.ok()?,
+    };
+    Some((level.trim().parse().ok()?, record))
+}
+
+/// Seconds as shown on level files and results, such as 1'05"
+pub fn clock(seconds: u32) -> String {
+    format!("{}'{:02}\"", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(score: u32, time: u32) -> Record {
+        Record { score, time }
+    }
+
+    #[test]
+    fn records_keep_the_best_score_and_time() {
+        let mut records = Records::default();
+        assert!(records.unlocked(1));
+        assert!(!records.unlocked(2));
+
+        assert_eq!(records.add(1, record(500, 40)), None);
+        assert_eq!(records.add(1, record(800, 55)), Some(record(500, 40)));
+        assert_eq!(records.get(1), Some(record(800, 40)));
+        assert!(records.unlocked(2));
+        assert!(record(700, 40).beats(None));
+        assert!(!record(700, 40).beats(records.get(1)));
+        assert!(record(700, 39).beats(records.get(1)));
+
+        let text = records.to_text();
+        assert_eq!(Records::from_text(&text), records);
+        assert_eq!(Records::from_text("1 = lots\n2 = 10 20\n").get(1), None);
+    }
+}
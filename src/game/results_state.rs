@@ -0,0 +1,204 @@
+use async_trait::async_trait;
+use macroquad::prelude::*;
+use std::path::Path;
+
+use super::{
+    levels,
+    records::{clock, Record, RECORDS_FILE},
+    sound::Sounds,
+    states::{GameContext, Playable, StateType, Transition},
+};
+
+/// Points for every tile matched
+const CLEAR_POINTS: u32 = 50;
+/// Points for every second left on the clock
+const TIME_BONUS: u32 = 10;
+/// How long the score takes to count up
+const TALLY_SECONDS: f64 = 1.5;
+
+/// How a level went, handed from the level to the results screen
+#[derive(PartialEq, Clone, Debug)]
+pub struct LevelResults {
+    pub level: usize,
+    pub name: String,
+    pub time_used: u32,
+    pub time_left: u16,
+    pub moves: u32,
+    pub clears: u32,
+}
+
+impl LevelResults {
+    pub fn clear_points(&self) -> u32 {
+        self.clears * CLEAR_POINTS
+    }
+
+    pub fn time_bonus(&self) -> u32 {
+        self.time_left as u32 * TIME_BONUS
+    }
+
+    /// What the level adds to the score
+    pub fn points(&self) -> u32 {
+        self.clear_points() + self.time_bonus()
+    }
+}
+
+/// Points counted so far, `elapsed` seconds into the tally
+fn tally(points: u32, elapsed: f64) -> u32 {
+    (points as f64 * (elapsed / TALLY_SECONDS).clamp(0., 1.)) as u32
+}
+
+/// Shows how the level went, counts its points into the score and gives
+/// the password of the next level
+pub struct ResultsState {
+    font: Font,
+    results: LevelResults,
+    start_score: u32,
+    best: Option<Record>, // before this attempt
+    new_record: bool,
+    next_password: Option<String>, // none after the last level
+    tally_start: f64,
+}
+
+impl ResultsState {
+    pub fn new(ctx: &GameContext, results: LevelResults) -> Self {
+        let pack = &ctx.session.pack;
+        let next = results.level + 1;
+        let next_password = if next <= levels::count_levels(pack) {
+            let level = levels::load_level(pack, next);
+            Some(level.map_or("????".to_string(), |level| level.password))
+        } else {
+            None
+        };
+        ResultsState {
+            font: ctx.assets.font,
+            results,
+            start_score: 0,
+            best: None,
+            new_record: false,
+            next_password,
+            tally_start: 0.,
+        }
+    }
+
+    fn text_params(&self, color: Color) -> TextParams {
+        let (fs, fc, fa) = camera_font_scale(6.);
+        TextParams {
+            font: self.font,
+            font_size: fs,
+            font_scale: fc,
+            font_scale_aspect: fa,
+            color,
+        }
+    }
+
+    fn tallied(&self) -> u32 {
+        tally(self.results.points(), get_time() - self.tally_start)
+    }
+
+    fn draw(&self) {
+        clear_background(BLACK);
+        let r = &self.results;
+        let title = format!("LEVEL {} CLEAR!", r.name);
+        draw_text_ex(&title, 110., 30., self.text_params(GREEN));
+
+        let tallied = self.tallied();
+        let mut lines = vec![
+            ("TIME USED", clock(r.time_used)),
+            ("MOVES", r.moves.to_string()),
+            (
+                "CLEARS",
+                format!("{} X {} = {}", r.clears, CLEAR_POINTS, r.clear_points()),
+            ),
+            (
+                "TIME BONUS",
+                format!("{} X {} = {}", r.time_left, TIME_BONUS, r.time_bonus()),
+            ),
+            ("LEVEL POINTS", tallied.to_string()),
+            ("SCORE", (self.start_score + tallied).to_string()),
+        ];
+        match self.best {
+            Some(best) => lines.push(("BEST", format!("{}  {}", best.score, clock(best.time)))),
+            None => lines.push(("BEST", "-".to_string())),
+        }
+        let password = self
+            .next_password
+            .as_deref()
+            .unwrap_or("ALL LEVELS CLEARED");
+        lines.push(("NEXT PASSWORD", password.to_string()));
+
+        for (n, (label, value)) in lines.iter().enumerate() {
+            let y = 50. + n as f32 * 12.;
+            draw_text_ex(label, 60., y, self.text_params(LIGHTGRAY));
+            draw_text_ex(value, 160., y, self.text_params(WHITE));
+        }
+        if self.new_record && tallied == r.points() {
+            draw_text_ex("NEW RECORD!", 130., 160., self.text_params(YELLOW));
+        }
+        draw_text_ex("PRESS ENTER", 127., 180., self.text_params(GREEN));
+    }
+}
+
+#[async_trait]
+impl Playable for ResultsState {
+    async fn enter(&mut self, ctx: &mut GameContext) {
+        let record = Record {
+            score: self.results.points(),
+            time: self.results.time_used,
+        };
+        self.best = ctx.records.get(self.results.level);
+        self.new_record = record.beats(self.best);
+        ctx.records.add(self.results.level, record);
+        ctx.records.save(Path::new(RECORDS_FILE));
+
+        self.start_score = ctx.session.score;
+        ctx.session.score += self.results.points();
+        self.tally_start = get_time();
+    }
+
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition {
+        loop {
+            ctx.update();
+            set_camera(&Camera2D::from_display_rect(Rect::new(0., 0., 320., 200.)));
+            self.draw();
+
+            let counting = self.tallied() < self.results.points();
+            if counting {
+                ctx.mixer.play_sound(Sounds::Move).await;
+            }
+            if is_key_pressed(KeyCode::Enter) && counting {
+                self.tally_start = get_time() - TALLY_SECONDS;
+            } else if is_key_pressed(KeyCode::Enter) {
+                ctx.mixer.play_sound(Sounds::MenuSelect).await;
+                return match self.next_password {
+                    Some(_) => Transition::Replace(StateType::Playing(self.results.level + 1)),
+                    None => Transition::Pop,
+                };
+            }
+            ctx.next_frame().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tally_counts_up_to_the_level_points() {
+        let results = LevelResults {
+            level: 1,
+            name: "1-1".to_string(),
+            time_used: 40,
+            time_left: 20,
+            moves: 9,
+            clears: 6,
+        };
+        assert_eq!(results.points(), 6 * CLEAR_POINTS + 20 * TIME_BONUS);
+        assert_eq!(tally(results.points(), 0.), 0);
+        assert_eq!(
+            tally(results.points(), TALLY_SECONDS / 2.),
+            results.points() / 2
+        );
+        assert_eq!(tally(results.points(), 10.), results.points());
+    }
+}
@@ -1,59 +1,494 @@
+use std::convert::TryInto;
 
+use macroquad::{
+    audio::{self, PlaySoundParams, Sound},
+    time::{get_frame_time, get_time},
+};
 
-use macroquad::audio::{Sound, PlaySoundParams};
+use super::{
+    assets::{AssetError, Loader},
+    config::Config,
+};
 
+/// Says which file plays for each of the `Sounds`
+const MANIFEST: &str = "sound/sounds.txt";
+
+/// Seconds it takes a track to fade in or out
+const FADE_SECONDS: f32 = 0.5;
+
+/// Most sound effects playing at once
+const MAX_VOICES: usize = 4;
+
+/// Seconds before a sound effect can start over
+const RETRIGGER_SECONDS: f64 = 0.08;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Sounds {
-    MOVE,
     LevelIntro,
-    Playing,
-    Collided,
+    Music1,
+    Music2,
+    Music3,
+    Move,
+    Grab,
+    Release,
+    Land,
+    Elevator,
+    Bounce,
+    Match,
+    LevelClear,
+    TimeWarning, // no trigger yet, levels have no countdown
+    TimeUp,      // no trigger yet, levels have no countdown
+    MenuMove,
+    MenuSelect,
+    GameOver, // no trigger yet, there are no lives to lose
+}
+
+impl Sounds {
+    pub const ALL: [Sounds; 17] = [
+        Sounds::LevelIntro,
+        Sounds::Music1,
+        Sounds::Music2,
+        Sounds::Music3,
+        Sounds::Move,
+        Sounds::Grab,
+        Sounds::Release,
+        Sounds::Land,
+        Sounds::Elevator,
+        Sounds::Bounce,
+        Sounds::Match,
+        Sounds::LevelClear,
+        Sounds::TimeWarning,
+        Sounds::TimeUp,
+        Sounds::MenuMove,
+        Sounds::MenuSelect,
+        Sounds::GameOver,
+    ];
+
+    /// Tracks the worlds take turns to play, `Music: <n>` in a level file
+    /// picks the nth one instead
+    pub const PLAYLIST: [Sounds; 3] = [Sounds::Music1, Sounds::Music2, Sounds::Music3];
+
+    /// Track played throughout a world
+    pub fn world_music(world: usize) -> Sounds {
+        Sounds::PLAYLIST[world.saturating_sub(1) % Sounds::PLAYLIST.len()]
+    }
+
+    /// Whether it plays on the music bus, one track at a time
+    pub fn is_music(self) -> bool {
+        self == Sounds::LevelIntro || Sounds::PLAYLIST.contains(&self)
+    }
+
+    /// Which sound effects win when too many play at once, the higher the better
+    pub fn priority(self) -> u8 {
+        match self {
+            Sounds::LevelClear | Sounds::TimeUp | Sounds::GameOver => 3,
+            Sounds::Match | Sounds::TimeWarning | Sounds::MenuSelect => 2,
+            Sounds::Grab | Sounds::Release | Sounds::Land | Sounds::Bounce => 1,
+            _ => 0,
+        }
+    }
+
+    /// Name of the event in the manifest
+    pub fn name(self) -> &'static str {
+        match self {
+            Sounds::LevelIntro => "level_intro",
+            Sounds::Music1 => "music_1",
+            Sounds::Music2 => "music_2",
+            Sounds::Music3 => "music_3",
+            Sounds::Move => "move",
+            Sounds::Grab => "grab",
+            Sounds::Release => "release",
+            Sounds::Land => "land",
+            Sounds::Elevator => "elevator",
+            Sounds::Bounce => "bounce",
+            Sounds::Match => "match",
+            Sounds::LevelClear => "level_clear",
+            Sounds::TimeWarning => "time_warning",
+            Sounds::TimeUp => "time_up",
+            Sounds::MenuMove => "menu_move",
+            Sounds::MenuSelect => "menu_select",
+            Sounds::GameOver => "game_over",
+        }
+    }
+}
+
+/// Length in seconds of an Ogg Vorbis file, zero if it can't be told. That's
+/// the granule position, in samples, of the last page over the sample rate
+/// in the identification header.
+pub fn ogg_seconds(bytes: &[u8]) -> f32 {
+    let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+    let rfind = |needle: &[u8]| bytes.windows(needle.len()).rposition(|w| w == needle);
+    let (header, last_page) = match (find(b"\x01vorbis"), rfind(b"OggS")) {
+        (Some(header), Some(last_page)) => (header, last_page),
+        _ => return 0.,
+    };
+    let rate = bytes.get(header + 12..header + 16);
+    let samples = bytes.get(last_page + 6..last_page + 14);
+    match (rate, samples) {
+        (Some(rate), Some(samples)) => {
+            let rate = u32::from_le_bytes(rate.try_into().unwrap());
+            let samples = i64::from_le_bytes(samples.try_into().unwrap());
+            if rate == 0 || samples < 0 {
+                0.
+            } else {
+                samples as f32 / rate as f32
+            }
+        }
+        _ => 0.,
+    }
+}
+
+/// Reads the `event = file` lines of the manifest, returning the file of
+/// every event in the order of `Sounds::ALL`
+fn parse_manifest(text: &str) -> Result<Vec<String>, String> {
+    let mut files = vec![None; Sounds::ALL.len()];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, file) = line
+            .split_once('=')
+            .ok_or_else(|| format!("cannot read {:?}", line))?;
+        let event = Sounds::ALL
+            .iter()
+            .position(|s| s.name() == name.trim())
+            .ok_or_else(|| format!("unknown sound event {:?}", name.trim()))?;
+        files[event] = Some(file.trim().to_string());
+    }
+    Sounds::ALL
+        .iter()
+        .zip(files)
+        .map(|(event, file)| file.ok_or_else(|| format!("no file for {}", event.name())))
+        .collect()
+}
+
+/// A music track playing, or fading in or out
+struct Track {
+    event: Sounds,
+    fade: f32,   // from 0, silent, to 1, full volume
+    target: f32, // what `fade` is heading for
+}
+
+/// A sound effect being played
+struct Voice {
+    event: Sounds,
+    start: f64,
+    end: f64,
+}
+
+/// Decides which of the sound effects asked for in a frame get played
+#[derive(Default)]
+struct Voices {
+    queued: Vec<Sounds>, // asked for this frame, each one once
+    playing: Vec<Voice>,
 }
 
+impl Voices {
+    fn queue(&mut self, snd: Sounds) {
+        if !self.queued.contains(&snd) {
+            self.queued.push(snd);
+        }
+    }
+
+    /// Empties the queue, highest priority first. Returns the sounds to play,
+    /// and the ones to cut short to make room for them. Anything that just
+    /// started, or that can't beat what's playing, is dropped.
+    fn schedule(&mut self, now: f64, length: impl Fn(Sounds) -> f32) -> (Vec<Sounds>, Vec<Sounds>) {
+        let mut queued = std::mem::take(&mut self.queued);
+        queued.sort_by_key(|snd| std::cmp::Reverse(snd.priority()));
+        self.playing.retain(|v| v.end > now);
+        let (mut play, mut stop) = (vec![], vec![]);
+        for snd in queued {
+            if self
+                .playing
+                .iter()
+                .any(|v| v.event == snd && now - v.start < RETRIGGER_SECONDS)
+            {
+                continue;
+            }
+            if self.playing.len() >= MAX_VOICES {
+                // the least important, and of those the oldest, gives way
+                let weakest = (0..self.playing.len())
+                    .min_by(|&a, &b| {
+                        let (a, b) = (&self.playing[a], &self.playing[b]);
+                        let by_start = a.start.partial_cmp(&b.start).unwrap();
+                        a.event.priority().cmp(&b.event.priority()).then(by_start)
+                    })
+                    .unwrap();
+                if self.playing[weakest].event.priority() >= snd.priority() {
+                    continue;
+                }
+                // stopping a sound stops every copy of it that is playing
+                let cut = self.playing[weakest].event;
+                self.playing.retain(|v| v.event != cut);
+                stop.push(cut);
+            }
+            self.playing.push(Voice {
+                event: snd,
+                start: now,
+                end: now + length(snd) as f64,
+            });
+            play.push(snd);
+        }
+        (play, stop)
+    }
+}
+
+/// Plays sound effects and music on two buses of their own volume
 pub struct Mixer {
-    level_intro: Sound,
-    player_move: Sound,
-    playing: Sound,
-    collided: Sound,
+    sounds: Vec<Sound>, // in the order of `Sounds::ALL`
+    lengths: Vec<f32>,  // in seconds, same order
+    music_volume: f32,
+    sfx_volume: f32,
+    muted: bool,
+    tracks: Vec<Track>, // the last one is the current track
+    voices: Voices,
 }
+
 impl Mixer {
-    pub async fn new() -> Self {
+    /// Number of files loaded by `Mixer::new`, the manifest included
+    pub const COUNT: usize = Sounds::ALL.len() + 1;
+
+    pub async fn new(loader: &mut Loader) -> Result<Self, AssetError> {
+        let manifest = loader.text(MANIFEST).await?;
+        let files = parse_manifest(&manifest).map_err(|reason| AssetError {
+            path: MANIFEST.to_owned(),
+            reason,
+        })?;
+        let mut sounds = vec![];
+        let mut lengths = vec![];
+        for file in files {
+            let (sound, length) = loader.sound(&format!("sound/{}", file)).await?;
+            sounds.push(sound);
+            lengths.push(length);
+        }
+        Ok(Mixer {
+            sounds,
+            lengths,
+            music_volume: 1.,
+            sfx_volume: 1.,
+            muted: false,
+            tracks: vec![],
+            voices: Voices::default(),
+        })
+    }
+
+    /// A mixer without sounds, for running states without a window
+    #[cfg(test)]
+    pub fn empty() -> Self {
         Mixer {
-            level_intro: macroquad::audio::load_sound("sound/ogg/1 - Track 1.ogg")
-                .await
-                .unwrap(),
-            playing: macroquad::audio::load_sound("sound/ogg/2 - Track 2.ogg")
-                .await
-                .unwrap(),
-            player_move: macroquad::audio::load_sound("sound/ogg/SFX 2.ogg")
-                .await
-                .unwrap(),
-            collided: macroquad::audio::load_sound("sound/ogg/SFX 17.ogg")
-                .await
-                .unwrap(),
+            sounds: vec![],
+            lengths: vec![],
+            music_volume: 1.,
+            sfx_volume: 1.,
+            muted: true,
+            tracks: vec![],
+            voices: Voices::default(),
         }
     }
-    pub fn stop_music(&mut self) {
-        macroquad::audio::stop_sound(self.playing);
 
+    fn sound(&self, snd: Sounds) -> Sound {
+        self.sounds[snd as usize]
     }
-    pub async fn play_sound(&mut self, snd: Sounds) {
-        // let mut ctx = AudioContext::new();
-        match snd {
-            Sounds::MOVE => {
-                macroquad::audio::play_sound_once(self.player_move);
-            }
-            Sounds::LevelIntro => {
-                macroquad::audio::play_sound_once(self.level_intro);
-            }
-            Sounds::Playing => {                
-                macroquad::audio::play_sound(self.playing, PlaySoundParams{
-                    looped: true,
-                    volume: 1.,
-                });
+
+    /// How long a sound plays for, in seconds
+    pub fn length(&self, snd: Sounds) -> f32 {
+        self.lengths[snd as usize]
+    }
+
+    /// Picks up the volumes and mute setting
+    pub fn apply_config(&mut self, config: &Config) {
+        self.music_volume = config.music_volume.clamp(0., 1.);
+        self.sfx_volume = config.sfx_volume.clamp(0., 1.);
+        self.muted = config.muted;
+        for track in self.tracks.iter() {
+            audio::set_sound_volume(self.sound(track.event), self.track_volume(track));
+        }
+    }
+
+    fn track_volume(&self, track: &Track) -> f32 {
+        if self.muted {
+            0.
+        } else {
+            self.music_volume * track.fade
+        }
+    }
+
+    /// Plays the sound effects asked for since the last frame and fades the
+    /// tracks in and out, to be called once a frame
+    pub fn update(&mut self) {
+        let lengths = &self.lengths;
+        let (play, stop) = self
+            .voices
+            .schedule(get_time(), |snd| lengths[snd as usize]);
+        for snd in stop {
+            audio::stop_sound(self.sound(snd));
+        }
+        let volume = if self.muted { 0. } else { self.sfx_volume };
+        for snd in play {
+            audio::play_sound(
+                self.sound(snd),
+                PlaySoundParams {
+                    looped: false,
+                    volume,
+                },
+            );
+        }
+
+        let step = get_frame_time() / FADE_SECONDS;
+        for track in self.tracks.iter_mut() {
+            if track.fade < track.target {
+                track.fade = (track.fade + step).min(track.target);
+            } else {
+                track.fade = (track.fade - step).max(track.target);
             }
-            Sounds::Collided => {
-                macroquad::audio::play_sound_once(self.collided);
+        }
+        for track in self.tracks.iter() {
+            let sound = self.sound(track.event);
+            if track.fade == 0. && track.target == 0. {
+                audio::stop_sound(sound);
+            } else {
+                audio::set_sound_volume(sound, self.track_volume(track));
             }
         }
+        self.tracks.retain(|t| t.fade > 0. || t.target > 0.);
+    }
+
+    /// Fades out whatever music is playing
+    pub fn stop_music(&mut self) {
+        for track in self.tracks.iter_mut() {
+            track.target = 0.;
+        }
+    }
+
+    /// Music starts right away, sound effects wait for the next `update`
+    pub async fn play_sound(&mut self, snd: Sounds) {
+        if snd.is_music() {
+            self.play_music(snd);
+        } else {
+            self.voices.queue(snd);
+        }
+    }
+
+    /// Starts a track, fading out the one playing. The new track fades in
+    /// as well, unless it starts from silence.
+    fn play_music(&mut self, snd: Sounds) {
+        let switching = self.tracks.iter().any(|t| t.target > 0.);
+        self.stop_music();
+        self.tracks.retain(|t| t.event != snd);
+        audio::stop_sound(self.sound(snd));
+        let track = Track {
+            event: snd,
+            fade: if switching { 0. } else { 1. },
+            target: 1.,
+        };
+        audio::play_sound(
+            self.sound(snd),
+            PlaySoundParams {
+                looped: snd != Sounds::LevelIntro,
+                volume: self.track_volume(&track),
+            },
+        );
+        self.tracks.push(track);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn every_sound_has_a_file() {
+        let manifest = std::fs::read_to_string(MANIFEST).unwrap();
+        let files = parse_manifest(&manifest).unwrap();
+        for file in files.iter() {
+            assert!(Path::new("sound").join(file).exists(), "{} is missing", file);
+        }
+        // the intro jingle isn't looped as a world's music, and every track
+        // of the playlist is a different one
+        let intro = &files[Sounds::LevelIntro as usize];
+        assert_eq!(files.iter().filter(|f| *f == intro).count(), 1);
+        for (n, track) in Sounds::PLAYLIST.iter().enumerate() {
+            let file = &files[*track as usize];
+            assert!(!Sounds::PLAYLIST[..n].iter().any(|t| &files[*t as usize] == file));
+        }
+        assert!(parse_manifest("move = a.ogg").is_err());
+        assert!(parse_manifest("jump = a.ogg").is_err());
+    }
+
+    #[test]
+    fn voices_are_limited_by_priority() {
+        let mut voices = Voices::default();
+        for _ in 0..3 {
+            voices.queue(Sounds::Match);
+        }
+        voices.queue(Sounds::Move);
+        assert_eq!(
+            voices.schedule(0., |_| 1.),
+            (vec![Sounds::Match, Sounds::Move], vec![])
+        );
+
+        // too soon for the same sound again
+        voices.queue(Sounds::Match);
+        assert_eq!(voices.schedule(0.01, |_| 1.), (vec![], vec![]));
+
+        voices.queue(Sounds::Land);
+        voices.queue(Sounds::Bounce);
+        voices.queue(Sounds::MenuMove);
+        let (play, stop) = voices.schedule(0.5, |_| 1.);
+        assert_eq!(play, [Sounds::Land, Sounds::Bounce]);
+        assert!(stop.is_empty());
+
+        // all voices taken, only something more important gets through
+        voices.queue(Sounds::Elevator);
+        voices.queue(Sounds::LevelClear);
+        assert_eq!(
+            voices.schedule(0.6, |_| 1.),
+            (vec![Sounds::LevelClear], vec![Sounds::Move])
+        );
+
+        // finished sounds free their voice
+        voices.queue(Sounds::Elevator);
+        assert_eq!(
+            voices.schedule(1.55, |_| 1.),
+            (vec![Sounds::Elevator], vec![])
+        );
+    }
+
+    #[test]
+    fn full_voices_give_way_to_the_least_important_and_oldest() {
+        let mut voices = Voices::default();
+        voices.queue(Sounds::Grab);
+        voices.queue(Sounds::Move);
+        voices.schedule(0., |_| 1.);
+        voices.queue(Sounds::Land);
+        voices.queue(Sounds::MenuMove);
+        voices.schedule(0.1, |_| 1.);
+        assert_eq!(voices.playing.len(), MAX_VOICES);
+
+        // the older of the two unimportant sounds is cut, the other one
+        // is as important as anything asked for after it
+        voices.queue(Sounds::Elevator);
+        voices.queue(Sounds::Bounce);
+        assert_eq!(
+            voices.schedule(0.2, |_| 1.),
+            (vec![Sounds::Bounce], vec![Sounds::Move])
+        );
+        voices.queue(Sounds::Match);
+        assert_eq!(
+            voices.schedule(0.3, |_| 1.),
+            (vec![Sounds::Match], vec![Sounds::MenuMove])
+        );
+        voices.queue(Sounds::Release);
+        assert_eq!(voices.schedule(0.4, |_| 1.), (vec![], vec![]));
+        assert_eq!(voices.playing.len(), MAX_VOICES);
+    }
+
+    #[test]
+    fn ogg_lengths() {
+        let intro = std::fs::read("sound/ogg/1 - Track 1.ogg").unwrap();
+        assert!((ogg_seconds(&intro) - 3.84).abs() < 0.01);
+        assert_eq!(ogg_seconds(b"not a sound"), 0.);
     }
 }
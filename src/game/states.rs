@@ -1,56 +1,396 @@
+use std::path::{Path, PathBuf};
+
 use super::{
+    args::Args,
+    assets::{self, Assets},
+    config::{Config, CONFIG_FILE},
     game_logic::PlayingState,
+    help_state::HelpState,
+    level_select_state::LevelSelectState,
     levels,
-    menu_state::{MenuState}
+    menu_state::MenuState,
+    options_state::OptionsState,
+    records::Records,
+    results_state::{LevelResults, ResultsState},
+    transition::{curtain_camera, draw_frame, Curtain, Effect},
 };
 use crate::game::sound::Mixer;
 use async_trait::async_trait;
+use macroquad::{
+    input::{get_last_key_pressed, is_key_pressed, KeyCode},
+    prelude::{
+        get_time, next_frame, pop_camera_state, push_camera_state, screen_height, screen_width,
+        Texture2D,
+    },
+    window::get_internal_gl,
+};
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum StateType {
     Menu,
     Playing(usize),
+    Options,
+    LevelSelect,
+    Results(LevelResults),
     ExitConfirm,
     Help,
     Quit,
 }
 
-pub struct GameState {
-    pub state: StateType,
+/// What the state machine should do once the running state hands control back
+#[derive(PartialEq, Clone, Debug)]
+pub enum Transition {
+    /// Suspend the current state and run a new one on top of it
+    Push(StateType),
+    /// Drop the current state and resume the one underneath
+    Pop,
+    /// Drop the current state and run a new one in its place
+    Replace(StateType),
+    Quit,
 }
 
-#[async_trait]
-pub trait Playable {
-    async fn run(&mut self, mixer: &mut Mixer) -> StateType;
+impl Transition {
+    /// The curtain drawn between the two states
+    fn effect(&self) -> Option<Effect> {
+        match self {
+            Transition::Push(StateType::Playing(_))
+            | Transition::Replace(StateType::Playing(_)) => Some(Effect::Bricks),
+            Transition::Replace(StateType::Results(_)) => Some(Effect::Wipe),
+            Transition::Quit => None,
+            _ => Some(Effect::Fade),
+        }
+    }
+}
+
+/// Data that outlives a single state, such as the running score
+pub struct Session {
+    pub score: u32,
+    pub pack: PathBuf, // directory the level files are read from
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            score: 0,
+            pack: PathBuf::from("levels"),
+        }
+    }
+}
+
+/// Everything a state gets access to while it runs
+pub struct GameContext {
+    pub assets: Assets,
+    pub config: Config,
+    pub args: Args, // overrides `config` for this run, never saved
+    pub mixer: Mixer,
+    pub session: Session,
+    pub records: Records,
+    pub curtain: Option<Curtain>, // while a transition uncovers the screen
+    pub last_frame: Texture2D,    // the screen as last drawn, to close a curtain over
+}
+
+impl GameContext {
+    /// What has to happen every frame whatever the state, such as fading
+    /// music and the mute key
+    pub fn update(&mut self) {
+        if is_key_pressed(KeyCode::M) {
+            self.config.muted = !self.config.muted;
+            self.mixer.apply_config(&self.config);
+            self.save_config();
+        }
+        self.mixer.update();
+    }
+
+    /// Saves the settings as they'd be without the command line's overrides
+    pub fn save_config(&self) {
+        let path = Path::new(CONFIG_FILE);
+        let mut config = self.config.clone();
+        self.args.unapply(&mut config, &Config::load(path));
+        config.save(path);
+    }
+
+    /// Ends the frame, like `next_frame`, with the curtain of a transition
+    /// drawn over it. Any key skips the curtain, and goes no further.
+    pub async fn next_frame(&mut self) {
+        if let Some(curtain) = self.curtain {
+            match curtain.cover(get_time()) {
+                Some(cover) => {
+                    push_camera_state();
+                    curtain_camera();
+                    curtain.draw(cover, self.assets.brick_decoration);
+                    pop_camera_state();
+                }
+                None => self.curtain = None,
+            }
+        }
+        next_frame().await;
+        if self.curtain.is_some() && get_last_key_pressed().is_some() {
+            self.curtain = None;
+            next_frame().await;
+        }
+    }
+
+    /// Keeps what has been drawn so far this frame, for a curtain to close over
+    fn grab_frame(&mut self) {
+        let (width, height) = (screen_width() as u16, screen_height() as u16);
+        if (self.last_frame.width(), self.last_frame.height()) != (width as f32, height as f32) {
+            let pixels = vec![0; width as usize * height as usize * 4];
+            self.last_frame = Texture2D::from_rgba8(width, height, &pixels);
+        }
+        unsafe { get_internal_gl() }.flush();
+        self.last_frame.grab_screen();
+    }
+
+    /// Covers the last frame of a state that's leaving, which it drew
+    /// without ending the frame. Any key skips the curtain, and goes no further.
+    async fn close_curtain(&mut self, effect: Effect) {
+        self.grab_frame();
+        let curtain = Curtain::closing(effect, get_time());
+        while let Some(cover) = curtain.cover(get_time()) {
+            curtain_camera();
+            draw_frame(self.last_frame);
+            curtain.draw(cover, self.assets.brick_decoration);
+            next_frame().await;
+            if get_last_key_pressed().is_some() {
+                next_frame().await;
+                break;
+            }
+            self.mixer.update();
+        }
+    }
 }
 
-impl GameState {
-    pub async fn run(&self, mixer: &mut Mixer) -> StateType {
-        match self.state {
-            StateType::Menu => {
-                let mut menu = MenuState::new().await;
-                println!("Jumping to menu");
-//                let mut mixer = Mixer::new().await;
+#[async_trait]
+pub trait Playable: Send {
+    /// Called once when the state is pushed onto the stack
+    async fn enter(&mut self, _ctx: &mut GameContext) {}
+    /// Called once when the state is popped or replaced
+    async fn exit(&mut self, _ctx: &mut GameContext) {}
+    /// Called when another state is pushed on top of this one
+    async fn pause(&mut self, _ctx: &mut GameContext) {}
+    /// Called when the state on top of this one has been popped
+    async fn resume(&mut self, _ctx: &mut GameContext) {}
+
+    async fn run(&mut self, ctx: &mut GameContext) -> Transition;
+}
 
-                mixer.stop_music();
-                return  menu.run(mixer).await;
+/// Builds the state for a given `StateType`, if there's one implemented
+async fn create_state(state: &StateType, ctx: &GameContext) -> Option<Box<dyn Playable>> {
+    match state {
+        StateType::Menu => Some(Box::new(MenuState::new(&ctx.assets))),
+        StateType::Options => Some(Box::new(OptionsState::new(&ctx.assets, &ctx.config))),
+        StateType::LevelSelect => Some(Box::new(LevelSelectState::new(ctx))),
+        StateType::Help => Some(Box::new(HelpState::new(ctx))),
+        StateType::Results(results) => Some(Box::new(ResultsState::new(ctx, results.clone()))),
+        StateType::Playing(level) => match levels::load_level(&ctx.session.pack, *level) {
+            Ok(level_info) => {
+                let mut game = PlayingState::new(&ctx.assets);
+                game.set_level(level_info);
+                Some(Box::new(game))
+            }
+            Err(e) => {
+                // a broken level file ends the game, like a missing asset
+                assets::show_error(&format!("Cannot load {}", e)).await;
+                None
             }
-            StateType::Playing(level) => {
-                // Start the game
-                let level_info = levels::load_level(level);
+        },
+        _ => None,
+    }
+}
+
+/// A stack of states, only the topmost one runs
+pub struct StateMachine {
+    stack: Vec<Box<dyn Playable>>,
+}
+
+impl StateMachine {
+    pub fn new() -> Self {
+        StateMachine { stack: vec![] }
+    }
+
+    pub async fn push(&mut self, state: StateType, ctx: &mut GameContext) {
+        match create_state(&state, ctx).await {
+            Some(new_state) => self.push_state(new_state, ctx).await,
+            None => self.clear(ctx).await,
+        }
+    }
+
+    async fn push_state(&mut self, mut new_state: Box<dyn Playable>, ctx: &mut GameContext) {
+        if let Some(top) = self.stack.last_mut() {
+            top.pause(ctx).await;
+        }
+        new_state.enter(ctx).await;
+        self.stack.push(new_state);
+    }
 
-                let mut game = PlayingState::new().await;
+    pub async fn pop(&mut self, ctx: &mut GameContext) {
+        if let Some(mut top) = self.stack.pop() {
+            top.exit(ctx).await;
+        }
+        if let Some(top) = self.stack.last_mut() {
+            top.resume(ctx).await;
+        }
+    }
+
+    pub async fn replace(&mut self, state: StateType, ctx: &mut GameContext) {
+        match create_state(&state, ctx).await {
+            Some(new_state) => self.replace_state(new_state, ctx).await,
+            None => self.clear(ctx).await,
+        }
+    }
 
-                game.set_level(level_info).await;
-                return game.run(mixer).await;
+    async fn replace_state(&mut self, mut new_state: Box<dyn Playable>, ctx: &mut GameContext) {
+        if let Some(mut top) = self.stack.pop() {
+            top.exit(ctx).await;
+        }
+        new_state.enter(ctx).await;
+        self.stack.push(new_state);
+    }
+
+    /// Exits every state, which ends the game
+    pub async fn clear(&mut self, ctx: &mut GameContext) {
+        while let Some(mut top) = self.stack.pop() {
+            top.exit(ctx).await;
+        }
+    }
 
-                
+    /// Runs the topmost state until the stack is empty
+    pub async fn run(&mut self, ctx: &mut GameContext) {
+        while let Some(top) = self.stack.last_mut() {
+            // states return with their last frame drawn but not shown yet
+            let transition = top.run(ctx).await;
+            let effect = transition.effect();
+            if let Some(effect) = effect {
+                ctx.close_curtain(effect).await;
+            }
+            match transition {
+                Transition::Push(state) => self.push(state, ctx).await,
+                Transition::Pop => self.pop(ctx).await,
+                Transition::Replace(state) => self.replace(state, ctx).await,
+                Transition::Quit => self.clear(ctx).await,
             }
-            _ => StateType::Quit,
+            // uncovered once the next state is ready, however long that took
+            ctx.curtain = effect.map(|effect| Curtain::opening(effect, get_time()));
+        }
+    }
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+/// Runs a future that never waits, such as a state's hooks in tests
+pub fn block_on<F: std::future::Future>(mut future: F) -> F::Output {
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    let mut future = unsafe { std::pin::Pin::new_unchecked(&mut future) };
+    match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the future waited"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// A state that writes down every hook called on it
+    struct Probe {
+        name: &'static str,
+        log: Log,
+    }
+
+    impl Probe {
+        fn boxed(name: &'static str, log: &Log) -> Box<dyn Playable> {
+            Box::new(Probe {
+                name,
+                log: log.clone(),
+            })
+        }
+
+        fn note(&self, hook: &str) {
+            let entry = format!("{} {}", hook, self.name);
+            self.log.lock().unwrap().push(entry);
+        }
+    }
+
+    #[async_trait]
+    impl Playable for Probe {
+        async fn enter(&mut self, _ctx: &mut GameContext) {
+            self.note("enter");
+        }
+        async fn exit(&mut self, _ctx: &mut GameContext) {
+            self.note("exit");
+        }
+        async fn pause(&mut self, _ctx: &mut GameContext) {
+            self.note("pause");
         }
+        async fn resume(&mut self, _ctx: &mut GameContext) {
+            self.note("resume");
+        }
+        async fn run(&mut self, _ctx: &mut GameContext) -> Transition {
+            Transition::Pop
+        }
+    }
+
+    fn context() -> GameContext {
+        GameContext {
+            assets: Assets::empty(),
+            config: Config::default(),
+            args: Args::default(),
+            mixer: Mixer::empty(),
+            session: Session::default(),
+            records: Records::default(),
+            curtain: None,
+            last_frame: Texture2D::empty(),
+        }
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        log.lock().unwrap().drain(..).collect()
     }
 
-    pub fn new(state: StateType) -> Self {
-        GameState { state }
+    #[test]
+    fn states_are_paused_resumed_and_exited_in_stack_order() {
+        let log = Log::default();
+        let mut ctx = context();
+        let mut machine = StateMachine::new();
+
+        block_on(machine.push_state(Probe::boxed("menu", &log), &mut ctx));
+        block_on(machine.push_state(Probe::boxed("level", &log), &mut ctx));
+        assert_eq!(take(&log), ["enter menu", "pause menu", "enter level"]);
+
+        block_on(machine.replace_state(Probe::boxed("results", &log), &mut ctx));
+        assert_eq!(take(&log), ["exit level", "enter results"]);
+        assert_eq!(machine.stack.len(), 2);
+
+        block_on(machine.pop(&mut ctx));
+        assert_eq!(take(&log), ["exit results", "resume menu"]);
+
+        block_on(machine.push_state(Probe::boxed("options", &log), &mut ctx));
+        block_on(machine.clear(&mut ctx));
+        assert_eq!(
+            take(&log),
+            ["pause menu", "enter options", "exit options", "exit menu"]
+        );
+        assert!(machine.stack.is_empty());
+
+        block_on(machine.pop(&mut ctx));
+        assert!(take(&log).is_empty());
     }
 }
@@ -1,60 +1,104 @@
 use macroquad::prelude::Vec2;
 
-use super::playing_state::Direction;
+use super::{playing_state::Direction, tile_kind::TileKind};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Tile {
     pub id: u32,
     pub c: char,
+    pub kind: &'static TileKind,
     pub fade_step: u32,
     pub position: Vec2,
     pub velocity: Vec2,
     pub looping: bool,
     pub riding: bool,
     pub dragging_direction: Option<Direction>,
+    pub path: Option<ElevatorPath>, // only elevators have one
+}
+
+/// How an elevator travels, set with the `Elevator` lines of a level file
+#[derive(Clone, Debug)]
+pub struct ElevatorPath {
+    pub origin: Vec2,
+    pub direction: Vec2,    // unit vector it sets off along
+    pub speed: Option<f32>, // set by the level file, else it goes at the game speed's
+    pub range: Option<f32>, // furthest it goes from its origin, in pixels
+    pub pause: u32,         // frames it waits at either end
+    pub waiting: u32,       // frames left of the current pause
 }
 
 impl PartialEq for Tile {
     fn eq(&self, other: &Self) -> bool {
-        return self.id == other.id;
+        self.id == other.id
     }
 }
 
 impl Tile {
     pub fn new(id: u32, c: char, x: f32, y: f32) -> Tile {
+        let kind = TileKind::of(c).unwrap_or_else(|| panic!("unknown tile {:?}", c));
         let mut t = Tile {
-            id: id,
-            c: c,
+            id,
+            c,
+            kind,
             position: Vec2::new(x, y),
             velocity: Vec2::ZERO,
             looping: false,
             dragging_direction: None,
             riding: false,
             fade_step: 0,
+            path: None,
         };
 
-        t.position = Vec2::new(x, y);
-        t.c = c;
-        if c == '|' || c == '~' {
+        if kind.elevator.is_some() {
+            t.velocity = kind.velocity();
+            t.looping = true;
             t.riding = true;
+            t.path = Some(ElevatorPath {
+                origin: t.position,
+                direction: t.velocity.normalize(),
+                speed: None,
+                range: None,
+                pause: 0,
+                waiting: 0,
+            });
         }
-        return t;
+        t
     }
 
+    /// Whether the tile takes part in the game, that is, it isn't a wall
     pub fn is_playable(&self) -> bool {
-        return self.c != ' ' && self.c != '-';
+        self.c != ' ' && !self.kind.wall
+    }
+
+    pub fn is_elevator(&self) -> bool {
+        self.kind.elevator.is_some()
+    }
+
+    /// An elevator at either end of its path heads back after its pause,
+    /// or parks for good if it doesn't loop
+    pub fn turn_back(&mut self) {
+        if self.looping {
+            self.velocity *= -1.;
+        } else {
+            self.velocity = Vec2::ZERO;
+        }
+        if let Some(path) = self.path.as_mut() {
+            path.waiting = path.pause;
+        }
     }
 }
 
 pub enum TileChange {
     Move,
-    Bounce,
+    Bounce, // an elevator can't push on, it turns back where it is
     Stop,
     FadeOut,
     // Copy(Tile),
     Jump(Vec2),
-    VelocityUpdate(Vec2),
-    StartRiding(Vec2),
-    Fall,
+    Carry(Vec2, Vec2), // new position and velocity of a tile riding an elevator
+    Arrive(Vec2),      // an elevator reached either end of its path at this position
+    Wait,              // an elevator is pausing at one end
+    Fall(f32),    // falling, at this speed from now on
+    Land(Vec2),   // a falling tile hits whatever is below, settling right on top of it
     RidingFlag(bool),
 }
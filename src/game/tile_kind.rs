@@ -0,0 +1,93 @@
+use macroquad::prelude::*;
+
+use super::playing_state::{Direction, SPEED};
+
+/// Everything the game needs to know about one character of a level file
+#[derive(Debug)]
+pub struct TileKind {
+    pub c: char,
+    pub sprite: u32, // image offset of the tile in the main image
+    pub tint: Color,
+    pub matchable: bool, // vanishes when touching a tile of the same kind
+    pub movable: bool,   // can be dragged by the player
+    pub wall: bool,      // never moves, never checked for collisions of its own
+    pub elevator: Option<(Direction, f32)>, // starting direction and speed
+}
+
+const fn block(c: char, sprite: u32, tint: Color) -> TileKind {
+    TileKind {
+        c,
+        sprite,
+        tint,
+        matchable: true,
+        movable: true,
+        wall: false,
+        elevator: None,
+    }
+}
+
+const fn elevator(c: char, direction: Direction) -> TileKind {
+    TileKind {
+        c,
+        sprite: 112,
+        tint: WHITE,
+        matchable: false,
+        movable: false,
+        wall: false,
+        elevator: Some((direction, SPEED)),
+    }
+}
+
+pub const EMPTY: TileKind = TileKind {
+    c: ' ',
+    sprite: 0,
+    tint: BLANK,
+    matchable: false,
+    movable: false,
+    wall: false,
+    elevator: None,
+};
+
+/// Every tile kind that can appear in a level file
+pub static KINDS: [TileKind; 13] = [
+    EMPTY,
+    TileKind {
+        c: '-',
+        sprite: 128,
+        tint: WHITE,
+        matchable: false,
+        movable: false,
+        wall: true,
+        elevator: None,
+    },
+    block('G', 0, WHITE),
+    block('X', 16, WHITE),
+    block('E', 32, WHITE),
+    block('B', 48, WHITE),
+    block('P', 64, WHITE),
+    block('C', 80, WHITE),
+    block('D', 96, WHITE),
+    // Used by levels such as 10-10, tiles.png has no sprite for it
+    block('T', 0, ORANGE),
+    block('?', 112, WHITE),
+    elevator('|', Direction::Up),
+    elevator('~', Direction::Right),
+];
+
+impl TileKind {
+    /// Looks up the kind of a level character
+    pub fn of(c: char) -> Option<&'static TileKind> {
+        KINDS.iter().find(|k| k.c == c)
+    }
+
+    /// Initial velocity of an elevator, zero for anything else
+    pub fn velocity(&self) -> Vec2 {
+        match &self.elevator {
+            Some((Direction::Up, speed)) => Vec2::new(0., -speed),
+            Some((Direction::Down, speed)) => Vec2::new(0., *speed),
+            Some((Direction::Left, speed)) => Vec2::new(-speed, 0.),
+            Some((Direction::Right, speed)) => Vec2::new(*speed, 0.),
+            _ => Vec2::ZERO,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use macroquad::prelude::*;
+
+/// Seconds a curtain takes to cover the screen, and again to uncover it
+const CURTAIN_SECONDS: f64 = 0.4;
+
+/// How one screen gives way to the next
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Effect {
+    /// Fades to black and back
+    Fade,
+    /// A black edge sweeps across from the left
+    Wipe,
+    /// A brick wall comes down like a curtain, the one around the board
+    Bricks,
+}
+
+/// An effect covering the screen as a state leaves, or uncovering it as
+/// the next one starts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Curtain {
+    pub effect: Effect,
+    start: f64,
+    closing: bool,
+}
+
+impl Curtain {
+    pub fn closing(effect: Effect, now: f64) -> Self {
+        Curtain {
+            effect,
+            start: now,
+            closing: true,
+        }
+    }
+
+    pub fn opening(effect: Effect, now: f64) -> Self {
+        Curtain {
+            effect,
+            start: now,
+            closing: false,
+        }
+    }
+
+    /// How much of the screen is covered at `now`, from 0 to 1, `None` once
+    /// the curtain is done
+    pub fn cover(&self, now: f64) -> Option<f32> {
+        let t = ((now - self.start) / CURTAIN_SECONDS).max(0.);
+        if t >= 1. {
+            return None;
+        }
+        Some(if self.closing { t } else { 1. - t } as f32)
+    }
+
+    /// Draws the curtain over a 320x200 camera
+    pub fn draw(&self, cover: f32, bricks: Texture2D) {
+        match self.effect {
+            Effect::Fade => draw_rectangle(0., 0., 320., 200., Color::new(0., 0., 0., cover)),
+            Effect::Wipe => draw_rectangle(0., 0., 320. * cover, 200., BLACK),
+            Effect::Bricks => {
+                // the wall's bottom row comes down from the top
+                let bottom = 200. * cover;
+                let rows = (bottom / 8.).ceil() as usize;
+                for row in 0..rows {
+                    let y = bottom - (row + 1) as f32 * 8.;
+                    let offset = if row % 2 > 0 { -8. } else { 0. };
+                    for x in 0..21 {
+                        draw_texture(bricks, offset + (x * 16) as f32, y, WHITE);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sets a 320x200 camera over the whole window, for drawing curtains
+pub fn curtain_camera() {
+    set_camera(&Camera2D::from_display_rect(Rect::new(0., 0., 320., 200.)));
+}
+
+/// Draws a frame grabbed from the screen so it fills the window
+pub fn draw_frame(frame: Texture2D) {
+    let params = DrawTextureParams {
+        dest_size: Some(Vec2::new(320., 200.)),
+        flip_y: true, // the screen is read bottom row first
+        ..Default::default()
+    };
+    draw_texture_ex(frame, 0., 0., WHITE, params);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curtains_cover_then_uncover() {
+        let closing = Curtain::closing(Effect::Fade, 10.);
+        assert_eq!(closing.cover(10.), Some(0.));
+        assert_eq!(closing.cover(10. + CURTAIN_SECONDS / 2.), Some(0.5));
+        assert_eq!(closing.cover(10. + CURTAIN_SECONDS), None);
+
+        let opening = Curtain::opening(Effect::Bricks, 10.);
+        assert_eq!(opening.cover(10.), Some(1.));
+        assert_eq!(opening.cover(10. + CURTAIN_SECONDS / 4.), Some(0.75));
+        assert_eq!(opening.cover(11.), None);
+    }
+}
@@ -1,38 +1,108 @@
-use game::{
-    states::{GameState, StateType},
+use brix::game::{
+    args::{self, Args},
+    assets::{self, AssetError, Assets, Loader},
+    config::{Config, CONFIG_FILE},
+    levels,
+    records::{Records, RECORDS_FILE},
+    sound::Mixer,
+    states::{GameContext, Session, StateMachine, StateType},
 };
-use macroquad::prelude::{Conf};
+use macroquad::prelude::{Conf, Texture2D};
+use std::{path::Path, sync::OnceLock};
 
-pub mod game;
+/// The command line, read once for both the window and the game
+fn args() -> &'static Args {
+    static ARGS: OnceLock<Args> = OnceLock::new();
+    ARGS.get_or_init(Args::from_env)
+}
+
+/// The saved settings with the command line's on top, read once as well
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let mut config = Config::load(Path::new(CONFIG_FILE));
+        args().apply(&mut config);
+        config
+    })
+}
 
 fn window_conf() -> Conf {
+    let config = config();
     Conf {
         window_title: "Puzznic".to_owned(),
-        window_width: 320 * 3,
-        window_height: 200 * 3,
+        window_width: 320 * config.scale as i32,
+        window_height: 200 * config.scale as i32,
+        fullscreen: config.fullscreen,
         ..Default::default()
     }
 }
+async fn load_assets() -> Result<(Assets, Mixer), AssetError> {
+    let mut loader = Loader::new(Assets::COUNT + Mixer::COUNT);
+    let assets = Assets::load(&mut loader).await?;
+    let mixer = Mixer::new(&mut loader).await?;
+    Ok((assets, mixer))
+}
+
 #[macroquad::main(window_conf)]
 async fn main() {
-    let mut state = GameState::new(StateType::Menu);
+    let args = args().clone();
+    let pack = args.pack();
+    if levels::count_levels(&pack) == 0 {
+        eprintln!("No levels in {}\n{}", pack.display(), args::USAGE);
+        std::process::exit(2);
+    }
+    let start = args.level.as_ref().map(|name| {
+        levels::find_level(&pack, name).unwrap_or_else(|| {
+            eprintln!("No level {} in {}", name, pack.display());
+            std::process::exit(2);
+        })
+    });
+
+    let (assets, mut mixer) = match load_assets().await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            assets::show_error(&e).await;
+            return;
+        }
+    };
 
-    let mut mixer = game::sound::Mixer::new().await;
-    while state.state != StateType::Quit {
-        let new_state = state.run(&mut mixer).await;
-        state = GameState::new(new_state);
+    let config = config().clone();
+    mixer.apply_config(&config);
+    let mut ctx = GameContext {
+        assets,
+        config,
+        args,
+        mixer,
+        session: Session {
+            pack,
+            ..Session::default()
+        },
+        records: Records::load(Path::new(RECORDS_FILE)),
+        curtain: None,
+        last_frame: Texture2D::empty(),
+    };
+
+    let mut machine = StateMachine::new();
+    machine.push(StateType::Menu, &mut ctx).await;
+    if let Some(level) = start {
+        machine.push(StateType::Playing(level), &mut ctx).await;
     }
+    machine.run(&mut ctx).await;
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::game::levels;
+    use brix::game::levels;
+    use std::path::Path;
     #[test]
     fn parse_levels() {
+        assert_eq!(levels::count_levels(Path::new("levels")), 160);
         for i in 1..161 {
             println!("Testing level {i}");
-            levels::load_level(i);
+            levels::validate_level(Path::new("levels"), i).unwrap();
         }
-
+        assert_eq!(levels::find_level(Path::new("levels"), "5-3"), Some(43));
+        assert_eq!(levels::find_level(Path::new("levels"), "43"), Some(43));
+        assert_eq!(levels::find_level(Path::new("levels"), "17-1"), None);
     }
 }